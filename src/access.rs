@@ -0,0 +1,183 @@
+//! Who can use the bot in each guild.
+//!
+//! Administrators always can. Everyone else needs to be on the guild's allow-list, have one of
+//! its allowed roles, or be in one of its open channels, where anyone can use it.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    env, fs, io,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, GuildId, RoleId, UserId};
+
+use crate::mentions::Mention;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuildAccess {
+    pub roles: BTreeSet<RoleId>,
+    pub users: BTreeSet<UserId>,
+    /// Anyone can use the bot in these channels, and in threads of them
+    pub channels: BTreeSet<ChannelId>,
+}
+
+/// Someone trying to use the bot, and where.
+pub struct Requester<'a> {
+    pub user: UserId,
+    pub roles: &'a [RoleId],
+    pub channel: ChannelId,
+    /// The channel a thread is in, or the category a channel is in
+    pub parent: Option<ChannelId>,
+    pub admin: bool,
+}
+
+impl GuildAccess {
+    pub fn allows(&self, who: &Requester) -> bool {
+        who.admin
+            || self.users.contains(&who.user)
+            || who.roles.iter().any(|role| self.roles.contains(role))
+            || self.channels.contains(&who.channel)
+            || who
+                .parent
+                .is_some_and(|parent| self.channels.contains(&parent))
+    }
+
+    /// Allows or disallows a role, member or channel, returning whether that changed anything.
+    pub fn set(&mut self, who: Mention, allowed: bool) -> bool {
+        fn set<T: Ord>(set: &mut BTreeSet<T>, id: T, allowed: bool) -> bool {
+            if allowed {
+                set.insert(id)
+            } else {
+                set.remove(&id)
+            }
+        }
+        match who {
+            Mention::Role(id) => set(&mut self.roles, id, allowed),
+            Mention::User(id) => set(&mut self.users, id, allowed),
+            Mention::Channel(id) => set(&mut self.channels, id, allowed),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roles.is_empty() && self.users.is_empty() && self.channels.is_empty()
+    }
+}
+
+/// Every guild's access, saved as JSON to `CALBOT_ACCESS_FILE` (`access.json` by default)
+/// whenever it changes.
+pub struct Access {
+    path: PathBuf,
+    guilds: Mutex<HashMap<GuildId, GuildAccess>>,
+}
+
+impl Access {
+    pub fn from_env() -> Self {
+        let path = env::var("CALBOT_ACCESS_FILE").unwrap_or_else(|_| "access.json".to_owned());
+        let guilds = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|why| {
+                println!("Ignoring unreadable {path}: {why}");
+                HashMap::new()
+            }),
+            Err(why) if why.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(why) => {
+                println!("Error reading {path}: {why}");
+                HashMap::new()
+            }
+        };
+        Self {
+            path: path.into(),
+            guilds: Mutex::new(guilds),
+        }
+    }
+
+    pub fn get(&self, guild_id: GuildId) -> GuildAccess {
+        let guilds = self.guilds.lock().unwrap();
+        guilds.get(&guild_id).cloned().unwrap_or_default()
+    }
+
+    /// Changes a guild's access and saves it. `change` returns whether it changed anything.
+    pub fn update(
+        &self,
+        guild_id: GuildId,
+        change: impl FnOnce(&mut GuildAccess) -> bool,
+    ) -> io::Result<bool> {
+        let mut guilds = self.guilds.lock().unwrap();
+        if !change(guilds.entry(guild_id).or_default()) {
+            return Ok(false);
+        }
+        guilds.retain(|_, access| !access.is_empty());
+        fs::write(&self.path, serde_json::to_string_pretty(&*guilds)?)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn requester(roles: &[RoleId]) -> Requester<'_> {
+        Requester {
+            user: UserId::new(1),
+            roles,
+            channel: ChannelId::new(10),
+            parent: Some(ChannelId::new(20)),
+            admin: false,
+        }
+    }
+
+    #[test]
+    fn allows() {
+        let mut access = GuildAccess::default();
+        let officer = [RoleId::new(5)];
+        assert!(!access.allows(&requester(&officer)));
+        assert!(access.allows(&Requester {
+            admin: true,
+            ..requester(&[])
+        }));
+
+        access.roles.insert(RoleId::new(5));
+        assert!(access.allows(&requester(&officer)));
+        assert!(!access.allows(&requester(&[RoleId::new(6)])));
+
+        access.roles.clear();
+        access.users.insert(UserId::new(1));
+        assert!(access.allows(&requester(&[])));
+
+        access.users.clear();
+        access.channels.insert(ChannelId::new(20));
+        assert!(access.allows(&requester(&[])));
+        assert!(!access.allows(&Requester {
+            parent: None,
+            ..requester(&[])
+        }));
+    }
+
+    #[test]
+    fn saved_as_json() {
+        let path = env::temp_dir().join(format!("calbot-access-{}.json", std::process::id()));
+        let access = Access {
+            path: path.clone(),
+            guilds: Mutex::default(),
+        };
+        let guild = GuildId::new(1234);
+        let changed = access.update(guild, |a| a.set(Mention::Role(RoleId::new(5)), true));
+        assert!(changed.unwrap());
+        assert!(!access
+            .update(guild, |a| a.set(Mention::Role(RoleId::new(5)), true))
+            .unwrap());
+
+        let json = fs::read_to_string(&path).unwrap();
+        let saved: HashMap<GuildId, GuildAccess> = serde_json::from_str(&json).unwrap();
+        assert_eq!(saved[&guild], access.get(guild));
+
+        // guilds left with nothing allowed aren't kept around
+        access
+            .update(guild, |a| a.set(Mention::Role(RoleId::new(5)), false))
+            .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{}");
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,253 @@
+use std::env;
+
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use serde::Deserialize;
+use serde_json::Value;
+use serenity::async_trait;
+
+use crate::parser::Error;
+
+const GROQ_ENDPOINT: &str = "https://api.groq.com/openai/v1/chat/completions";
+const GROQ_MODEL: &str = "llama-3.3-70b-versatile";
+const OLLAMA_ENDPOINT: &str = "http://localhost:11434/api/chat";
+const OLLAMA_MODEL: &str = "llama3.2";
+const MAX_COMPLETION_TOKEN: usize = 1024;
+
+/// A language model that turns a prompt into the raw text of its reply.
+///
+/// `parse_msg` only cares about the text that comes back, so anything that can answer a
+/// single-turn chat prompt can be used to extract events. Backends that support structured
+/// output should constrain the reply to `schema`, the rest can ignore it and rely on the prompt.
+#[async_trait]
+pub trait ExtractionBackend: Send + Sync {
+    async fn complete(&self, prompt: &str, schema: &Value) -> Result<String, Error>;
+}
+
+/// How an OpenAI-compatible server is asked for JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructuredOutput {
+    /// `response_format: json_schema`, the reply is guaranteed to match the schema
+    JsonSchema,
+    /// `response_format: json_object`, the reply is valid JSON but the schema isn't enforced
+    JsonObject,
+    /// Plain text completions, for servers that don't support `response_format`
+    Off,
+}
+
+impl StructuredOutput {
+    fn from_env(default: StructuredOutput) -> Self {
+        match env::var("CALBOT_LLM_STRUCTURED").as_deref() {
+            Ok("json_schema") => StructuredOutput::JsonSchema,
+            Ok("json_object") => StructuredOutput::JsonObject,
+            Ok("none") => StructuredOutput::Off,
+            Ok(other) => panic!("Unknown CALBOT_LLM_STRUCTURED: {other}"),
+            Err(_) => default,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatResponse {
+    choices: Vec<ChatChoice>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatChoice {
+    message: ChatMessage,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatMessage {
+    content: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct OllamaResponse {
+    message: Option<ChatMessage>,
+}
+
+/// Any server speaking the OpenAI `/chat/completions` protocol (OpenAI, Groq, llama.cpp,
+/// vLLM, LM Studio, ...).
+pub struct OpenAiCompatible {
+    endpoint: String,
+    model: String,
+    api_key: Option<String>,
+    structured: StructuredOutput,
+}
+
+impl OpenAiCompatible {
+    pub fn new(
+        endpoint: &str,
+        model: &str,
+        api_key: Option<String>,
+        structured: StructuredOutput,
+    ) -> Self {
+        Self {
+            endpoint: endpoint.to_owned(),
+            model: model.to_owned(),
+            api_key,
+            structured,
+        }
+    }
+}
+
+#[async_trait]
+impl ExtractionBackend for OpenAiCompatible {
+    async fn complete(&self, prompt: &str, schema: &Value) -> Result<String, Error> {
+        let mut req_body = serde_json::json!({
+            "model": self.model,
+            "max_completion_tokens": MAX_COMPLETION_TOKEN,
+            "messages": [
+            {
+                "role": "user",
+                "content": prompt,
+            }
+        ]});
+        match self.structured {
+            StructuredOutput::JsonSchema => {
+                req_body["response_format"] = serde_json::json!({
+                    "type": "json_schema",
+                    "json_schema": {
+                        "name": "calendar_events",
+                        "strict": true,
+                        "schema": schema,
+                    },
+                });
+            }
+            StructuredOutput::JsonObject => {
+                req_body["response_format"] = serde_json::json!({ "type": "json_object" });
+            }
+            StructuredOutput::Off => {}
+        }
+
+        let mut request = reqwest::Client::new()
+            .post(&self.endpoint)
+            .json(&req_body)
+            .header(CONTENT_TYPE, "application/json");
+        if let Some(key) = &self.api_key {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", key));
+        }
+
+        let resp: ChatResponse = request
+            .send()
+            .await
+            .map_err(Error::Reqwest)?
+            .json()
+            .await
+            .map_err(Error::Reqwest)?;
+
+        match resp.choices.into_iter().next() {
+            Some(choice) => Ok(choice.message.content),
+            None => Err(Error::NoResponse),
+        }
+    }
+}
+
+/// Groq's hosted models, which are served over the OpenAI protocol.
+///
+/// Only a few of Groq's models support `json_schema`, so JSON mode is the default.
+pub struct Groq(OpenAiCompatible);
+
+impl Groq {
+    pub fn new(api_key: String, model: Option<&str>, structured: StructuredOutput) -> Self {
+        Self(OpenAiCompatible::new(
+            GROQ_ENDPOINT,
+            model.unwrap_or(GROQ_MODEL),
+            Some(api_key),
+            structured,
+        ))
+    }
+}
+
+#[async_trait]
+impl ExtractionBackend for Groq {
+    async fn complete(&self, prompt: &str, schema: &Value) -> Result<String, Error> {
+        self.0.complete(prompt, schema).await
+    }
+}
+
+/// A model served by Ollama through its native `/api/chat` endpoint, which always enforces the
+/// schema.
+pub struct Ollama {
+    endpoint: String,
+    model: String,
+}
+
+impl Ollama {
+    pub fn new(endpoint: Option<&str>, model: Option<&str>) -> Self {
+        Self {
+            endpoint: endpoint.unwrap_or(OLLAMA_ENDPOINT).to_owned(),
+            model: model.unwrap_or(OLLAMA_MODEL).to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl ExtractionBackend for Ollama {
+    async fn complete(&self, prompt: &str, schema: &Value) -> Result<String, Error> {
+        let req_body = serde_json::json!({
+            "model": self.model,
+            "stream": false,
+            "format": schema,
+            "options": { "num_predict": MAX_COMPLETION_TOKEN },
+            "messages": [
+            {
+                "role": "user",
+                "content": prompt,
+            }
+        ]});
+
+        let resp: OllamaResponse = reqwest::Client::new()
+            .post(&self.endpoint)
+            .json(&req_body)
+            .send()
+            .await
+            .map_err(Error::Reqwest)?
+            .json()
+            .await
+            .map_err(Error::Reqwest)?;
+
+        resp.message.map(|m| m.content).ok_or(Error::NoResponse)
+    }
+}
+
+/// Builds the backend selected by the `CALBOT_BACKEND` env var.
+///
+/// - `groq` (default): needs `GROQ_API_KEY`
+/// - `openai`: needs `CALBOT_LLM_ENDPOINT` and `CALBOT_LLM_MODEL`, `CALBOT_LLM_API_KEY` is optional
+/// - `ollama`: `CALBOT_LLM_ENDPOINT` and `CALBOT_LLM_MODEL` are optional
+/// - `rules`: no LLM at all
+///
+/// `CALBOT_LLM_MODEL` overrides the default model for every backend, and `CALBOT_LLM_STRUCTURED`
+/// (`json_schema`, `json_object` or `none`) the structured output mode of `groq` and `openai`.
+///
+/// Returns `None` for `rules`, or for `groq` without an API key, in which case messages are only
+/// read by the offline extractor in `rules`.
+pub fn from_env() -> Option<Box<dyn ExtractionBackend>> {
+    let kind = env::var("CALBOT_BACKEND").unwrap_or_else(|_| "groq".to_owned());
+    let endpoint = env::var("CALBOT_LLM_ENDPOINT").ok();
+    let model = env::var("CALBOT_LLM_MODEL").ok();
+
+    match kind.to_lowercase().as_str() {
+        "groq" => {
+            let Ok(api_key) = env::var("GROQ_API_KEY") else {
+                println!("GROQ_API_KEY missing, only using the rule-based extractor");
+                return None;
+            };
+            Some(Box::new(Groq::new(
+                api_key,
+                model.as_deref(),
+                StructuredOutput::from_env(StructuredOutput::JsonObject),
+            )))
+        }
+        "openai" => Some(Box::new(OpenAiCompatible::new(
+            &endpoint.expect("CALBOT_LLM_ENDPOINT missing"),
+            &model.expect("CALBOT_LLM_MODEL missing"),
+            env::var("CALBOT_LLM_API_KEY").ok(),
+            StructuredOutput::from_env(StructuredOutput::JsonSchema),
+        ))),
+        "ollama" => Some(Box::new(Ollama::new(endpoint.as_deref(), model.as_deref()))),
+        "rules" => None,
+        other => panic!("Unknown CALBOT_BACKEND: {other}"),
+    }
+}
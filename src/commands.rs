@@ -0,0 +1,603 @@
+//! Application commands, which unlike @mentions work without the MESSAGE_CONTENT intent.
+
+use icalendar::Calendar;
+use serenity::all::{
+    ChannelType, CommandInteraction, CommandOptionType, CommandType, Context, CreateCommand,
+    CreateCommandOption, CreateInteractionResponse, CreateInteractionResponseMessage,
+    EditInteractionResponse, Mentionable, Permissions, ResolvedOption, ResolvedTarget,
+    ResolvedValue,
+};
+
+use crate::{
+    access::{Access, GuildAccess, Requester},
+    events::Handler,
+    feed, http,
+    mentions::{resolve_mentions, Mention},
+    parser::{parse_msg, parse_msg_with, Error, Overrides},
+    reactions,
+    review::{draft_buttons, draft_content, Draft},
+    rules::{date_phrase, time_phrase},
+    store::{GuildSettings, Origin, Store},
+    timezone::guild_timezone,
+    utils::{error_reply, local_date},
+    watch,
+};
+
+/// Name of the message context-menu command, shown under "Apps".
+const FROM_MESSAGE: &str = "Create calendar event";
+
+/// Every command the bot registers.
+pub fn all() -> Vec<CreateCommand> {
+    vec![
+        event_command(),
+        from_message_command(),
+        access_command(),
+        feed_command(),
+        settings_command(),
+        watch_command(),
+    ]
+}
+
+pub async fn run(ctx: &Context, handler: &Handler, cmd: &CommandInteraction) {
+    match cmd.data.name.as_str() {
+        "event" => event(ctx, handler, cmd).await,
+        FROM_MESSAGE => from_message(ctx, handler, cmd).await,
+        "access" => access(ctx, &handler.access, cmd).await,
+        "feed" => feed(ctx, &handler.store, cmd).await,
+        "settings" => settings(ctx, &handler.store, cmd).await,
+        "watch" => watch(ctx, &handler.store, cmd).await,
+        other => println!("Unknown command: {other}"),
+    }
+}
+
+/// `/event text:<announcement> [date] [start] [end] [location] [discord_event]`
+fn event_command() -> CreateCommand {
+    let option = |name: &str, description: &str| {
+        CreateCommandOption::new(CommandOptionType::String, name, description)
+    };
+    CreateCommand::new("event")
+        .description("Turn an announcement into a calendar event")
+        .dm_permission(false)
+        .add_option(option("text", "The announcement").required(true))
+        .add_option(option(
+            "date",
+            "Date of the event, e.g. \"tomorrow\", \"Friday\" or \"9/21\"",
+        ))
+        .add_option(option("start", "Start time, e.g. \"4pm\" or \"16:00\""))
+        .add_option(option("end", "End time, e.g. \"6pm\" or \"18:00\""))
+        .add_option(option("location", "Where the event is"))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "discord_event",
+            "Also make it a Discord event (defaults to the server's setting)",
+        ))
+}
+
+/// "Apps → Create calendar event" on any message. Anyone can use it, since the result is only
+/// shown to them.
+fn from_message_command() -> CreateCommand {
+    CreateCommand::new(FROM_MESSAGE)
+        .kind(CommandType::Message)
+        .dm_permission(false)
+}
+
+/// `/access allow|revoke who:<role or member>`, `/access open|close channel:<channel>` and
+/// `/access show`, see `access`.
+fn access_command() -> CreateCommand {
+    let subcommand = |name: &str, description: &str| {
+        CreateCommandOption::new(CommandOptionType::SubCommand, name, description)
+    };
+    let who = || {
+        CreateCommandOption::new(CommandOptionType::Mentionable, "who", "A role or member")
+            .required(true)
+    };
+    let channel = || {
+        CreateCommandOption::new(
+            CommandOptionType::Channel,
+            "channel",
+            "A channel, or a category for all of its channels",
+        )
+        .required(true)
+    };
+    CreateCommand::new("access")
+        .description("Choose who besides admins can use CalBot")
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+        .dm_permission(false)
+        .add_option(subcommand("allow", "Let a role or member use CalBot").add_sub_option(who()))
+        .add_option(
+            subcommand("revoke", "Stop a role or member using CalBot").add_sub_option(who()),
+        )
+        .add_option(
+            subcommand("open", "Let anyone use CalBot in a channel").add_sub_option(channel()),
+        )
+        .add_option(subcommand("close", "Undo opening a channel").add_sub_option(channel()))
+        .add_option(subcommand("show", "List who can use CalBot"))
+}
+
+/// `/feed [channel] [reset]`, see `feed`.
+fn feed_command() -> CreateCommand {
+    CreateCommand::new("feed")
+        .description("Subscribe to every event CalBot makes in this server")
+        .dm_permission(false)
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Channel,
+            "channel",
+            "Only events from this channel",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "reset",
+            "Admins only: stop the current link working and make a new one",
+        ))
+}
+
+/// `/settings [discord_events] [import_events] [import_channel] [reaction_emoji]`, which shows
+/// the settings when given nothing to change.
+fn settings_command() -> CreateCommand {
+    CreateCommand::new("settings")
+        .description("Change how CalBot works in this server")
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+        .dm_permission(false)
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "discord_events",
+            "Also make confirmed events Discord events",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "import_events",
+            "Post Discord events made in this server as calendar events",
+        ))
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Channel,
+                "import_channel",
+                "Where imported events are posted (defaults to this channel)",
+            )
+            .channel_types(vec![ChannelType::Text, ChannelType::News]),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "reaction_emoji",
+            "Reacting to a message with this emoji converts it (📅 by default)",
+        ))
+}
+
+/// `/watch on|off channel:<channel>` and `/watch show`, see `watch`.
+fn watch_command() -> CreateCommand {
+    let subcommand = |name: &str, description: &str| {
+        CreateCommandOption::new(CommandOptionType::SubCommand, name, description)
+    };
+    let channel = || {
+        CreateCommandOption::new(
+            CommandOptionType::Channel,
+            "channel",
+            "An announcement channel",
+        )
+        .channel_types(vec![ChannelType::Text, ChannelType::News])
+        .required(true)
+    };
+    CreateCommand::new("watch")
+        .description("Convert every event posted in a channel, without mentioning CalBot")
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+        .dm_permission(false)
+        .add_option(
+            subcommand("on", "Start converting events posted in a channel")
+                .add_sub_option(channel()),
+        )
+        .add_option(subcommand("off", "Stop watching a channel").add_sub_option(channel()))
+        .add_option(subcommand("show", "List the channels CalBot watches"))
+}
+
+/// Whoever ran `cmd`, and where.
+fn requester(cmd: &CommandInteraction) -> Requester<'_> {
+    let member = cmd.member.as_deref();
+    Requester {
+        user: cmd.user.id,
+        roles: member.map_or(&[], |m| &m.roles),
+        channel: cmd.channel_id,
+        parent: cmd.channel.as_ref().and_then(|c| c.parent_id),
+        admin: member
+            .and_then(|m| m.permissions)
+            .is_some_and(|p| p.administrator()),
+    }
+}
+
+/// Replies to the user who ran `cmd`, visible only to them.
+async fn reply_ephemeral(ctx: &Context, cmd: &CommandInteraction, content: impl Into<String>) {
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(content)
+            .ephemeral(true),
+    );
+    if let Err(why) = cmd.create_response(ctx, response).await {
+        println!("Error sending response: {why}");
+    }
+}
+
+/// Runs the same pipeline as an @mention, with the explicit options winning over extraction.
+async fn event(ctx: &Context, handler: &Handler, cmd: &CommandInteraction) {
+    let allowed = cmd
+        .guild_id
+        .is_some_and(|id| handler.access.get(id).allows(&requester(cmd)));
+    if !allowed {
+        reply_ephemeral(ctx, cmd, "Sorry! You're not allowed to use this bot here.").await;
+        return;
+    }
+
+    let mut text = "";
+    let mut overrides = Overrides::default();
+    let mut discord_events = handler.discord_events(cmd.guild_id);
+    for option in cmd.data.options() {
+        let value = match option.value {
+            ResolvedValue::String(value) => value,
+            ResolvedValue::Boolean(b) if option.name == "discord_event" => {
+                discord_events = b;
+                continue;
+            }
+            _ => continue,
+        };
+        let (field, parsed, example) = match option.name {
+            "text" => {
+                text = value;
+                continue;
+            }
+            "location" => {
+                overrides.location = Some(value.trim().to_owned());
+                continue;
+            }
+            "date" => (
+                &mut overrides.date,
+                date_phrase(value),
+                "\"Friday\" or \"9/21\"",
+            ),
+            "start" => (
+                &mut overrides.starttime,
+                time_phrase(value),
+                "\"4pm\" or \"16:00\"",
+            ),
+            "end" => (
+                &mut overrides.endtime,
+                time_phrase(value),
+                "\"6pm\" or \"18:00\"",
+            ),
+            _ => continue,
+        };
+        if parsed.is_none() {
+            reply_ephemeral(
+                ctx,
+                cmd,
+                format!(
+                    "Sorry! I couldn't read the {} `{value}`, try something like {example}.",
+                    option.name
+                ),
+            )
+            .await;
+            return;
+        }
+        *field = parsed;
+    }
+
+    // extraction can take longer than the 3 seconds Discord waits for a response
+    if let Err(why) = cmd.defer(ctx).await {
+        println!("Error deferring response: {why}");
+        return;
+    }
+
+    let tz = guild_timezone(cmd.guild_id);
+    let anchor_date = local_date(*cmd.id.created_at(), tz);
+    let content = resolve_mentions(ctx, cmd.guild_id, text, &[]);
+    let backend = handler.backend.as_deref();
+    let res = parse_msg_with(backend, &content, &anchor_date, tz, &overrides).await;
+    respond(ctx, handler, cmd, discord_events, false, res).await;
+}
+
+/// Converts the message the context menu was opened on, as if it had been replied to.
+async fn from_message(ctx: &Context, handler: &Handler, cmd: &CommandInteraction) {
+    let Some(ResolvedTarget::Message(target)) = cmd.data.target() else {
+        println!("{FROM_MESSAGE} without a target message");
+        return;
+    };
+    if let Err(why) = cmd.defer_ephemeral(ctx).await {
+        println!("Error deferring response: {why}");
+        return;
+    }
+
+    let tz = guild_timezone(cmd.guild_id);
+    let anchor = target.timestamp;
+    let anchor_date = local_date(*anchor, tz);
+    let content = resolve_mentions(ctx, cmd.guild_id, &target.content, &target.mentions);
+    let res = parse_msg(handler.backend.as_deref(), &content, &anchor_date, tz).await;
+    // anyone can use this, so it mustn't make public Discord events or add to the guild's feeds.
+    // The draft is only shown to whoever asked, so it isn't tied to the announcement either:
+    // edits and deletions of it only follow conversions everyone can see.
+    respond(ctx, handler, cmd, false, true, res).await;
+}
+
+/// Fills in the deferred response with a draft of the converted events, or why there aren't any.
+/// `private` events are kept out of the guild's feeds once they're confirmed.
+async fn respond(
+    ctx: &Context,
+    handler: &Handler,
+    cmd: &CommandInteraction,
+    discord_events: bool,
+    private: bool,
+    res: Result<Calendar, Error>,
+) {
+    let origin = Origin {
+        guild_id: cmd.guild_id,
+        channel_id: cmd.channel_id,
+        source: None,
+        author: cmd.user.id,
+        private,
+    };
+    let (response, draft) = match handler.publish(ctx, res, &origin).await {
+        Ok((calendar, cal_urls)) => {
+            let draft = Draft::new(&calendar, cal_urls, origin, discord_events);
+            let response = EditInteractionResponse::new()
+                .content(draft_content(&calendar))
+                .components(draft_buttons(&draft.events));
+            (response, Some(draft))
+        }
+        Err(e) => {
+            let reply =
+                error_reply(&e).unwrap_or_else(|| "Sorry! Something went wrong.".to_owned());
+            (EditInteractionResponse::new().content(reply), None)
+        }
+    };
+    match cmd.edit_response(ctx, response).await {
+        Ok(sent) => {
+            if let Some(draft) = draft {
+                handler.drafts.insert(sent.id, draft);
+            }
+        }
+        Err(why) => println!("Error sending response: {why}"),
+    }
+}
+
+/// Changes or shows who besides admins can use the bot in the guild.
+async fn access(ctx: &Context, access: &Access, cmd: &CommandInteraction) {
+    let Some(guild_id) = cmd.guild_id.filter(|_| requester(cmd).admin) else {
+        reply_ephemeral(
+            ctx,
+            cmd,
+            "Sorry! Only admins can change who can use this bot.",
+        )
+        .await;
+        return;
+    };
+    let Some(ResolvedOption {
+        name,
+        value: ResolvedValue::SubCommand(args),
+        ..
+    }) = cmd.data.options().into_iter().next()
+    else {
+        return;
+    };
+
+    let target = match args.first().map(|arg| &arg.value) {
+        Some(ResolvedValue::Role(role)) => Mention::Role(role.id),
+        Some(ResolvedValue::User(user, _)) => Mention::User(user.id),
+        Some(ResolvedValue::Channel(channel)) => Mention::Channel(channel.id),
+        _ => {
+            reply_ephemeral(ctx, cmd, access_list(&access.get(guild_id))).await;
+            return;
+        }
+    };
+    let allow = matches!(name, "allow" | "open");
+    let who = match target {
+        Mention::User(id) => id.mention().to_string(),
+        Mention::Role(id) => id.mention().to_string(),
+        Mention::Channel(id) => id.mention().to_string(),
+    };
+    let reply = match access.update(guild_id, |a| a.set(target, allow)) {
+        Ok(false) => "Nothing to change.".to_owned(),
+        Ok(true) => match (target, allow) {
+            (Mention::Channel(_), true) => format!("Anyone can now use CalBot in {who}."),
+            (Mention::Channel(_), false) => format!("{who} is no longer open to everyone."),
+            (_, true) => format!("{who} can now use CalBot."),
+            (_, false) => format!("{who} is no longer allowed to use CalBot."),
+        },
+        Err(why) => {
+            println!("Error saving access: {why}");
+            "Sorry! I couldn't save that.".to_owned()
+        }
+    };
+    reply_ephemeral(ctx, cmd, reply).await;
+}
+
+/// "Besides admins, CalBot can be used by: ..."
+fn access_list(access: &GuildAccess) -> String {
+    if access.is_empty() {
+        return "Only admins can use CalBot.".to_owned();
+    }
+    let list = |mentions: Vec<String>| mentions.join(", ");
+    let mut lines = vec!["Besides admins, CalBot can be used by:".to_owned()];
+    if !access.roles.is_empty() {
+        let roles = access.roles.iter().map(|id| id.mention().to_string());
+        lines.push(format!("- Roles: {}", list(roles.collect())));
+    }
+    if !access.users.is_empty() {
+        let users = access.users.iter().map(|id| id.mention().to_string());
+        lines.push(format!("- Members: {}", list(users.collect())));
+    }
+    if !access.channels.is_empty() {
+        let channels = access.channels.iter().map(|id| id.mention().to_string());
+        lines.push(format!("- Anyone in: {}", list(channels.collect())));
+    }
+    lines.join("\n")
+}
+
+/// Replies with the link to subscribe to the guild's (or a channel's) feed.
+async fn feed(ctx: &Context, store: &Store, cmd: &CommandInteraction) {
+    let (Some(guild_id), Some(public_url)) = (cmd.guild_id, http::public_url()) else {
+        reply_ephemeral(
+            ctx,
+            cmd,
+            "Sorry! Calendar feeds aren't set up for this bot.",
+        )
+        .await;
+        return;
+    };
+    let mut channel = None;
+    let mut reset = false;
+    for option in cmd.data.options() {
+        match option.value {
+            ResolvedValue::Channel(c) => channel = Some(c),
+            ResolvedValue::Boolean(b) => reset = b,
+            _ => {}
+        }
+    }
+    if reset && !requester(cmd).admin {
+        reply_ephemeral(ctx, cmd, "Sorry! Only admins can reset a feed.").await;
+        return;
+    }
+
+    let guild_name = ctx.cache.guild(guild_id).map(|g| g.name.clone());
+    let guild_name = guild_name.unwrap_or_else(|| "CalBot".to_owned());
+    let name = match channel.and_then(|c| c.name.as_ref()) {
+        Some(channel_name) => format!("{guild_name} #{channel_name}"),
+        None => guild_name,
+    };
+    let channel_id = channel.map(|c| c.id);
+    let token = match reset {
+        true => store.reset_feed(guild_id, channel_id),
+        false => Ok(()),
+    }
+    .and_then(|()| store.feed_token(guild_id, channel_id, &name));
+    let reply = match token {
+        Ok(token) => {
+            let (https, webcal) = feed::feed_urls(&public_url, &token);
+            format!(
+                "Subscribe to **{name}** with your calendar app's \"add calendar from URL\" \
+                 option:\n`{https}`\nOn Apple devices, open <{webcal}>.\n\
+                 Anyone with the link can see these events."
+            )
+        }
+        Err(why) => {
+            println!("Error getting feed: {why}");
+            "Sorry! Something went wrong.".to_owned()
+        }
+    };
+    reply_ephemeral(ctx, cmd, reply).await;
+}
+
+/// Changes or shows the guild's settings, see `store::GuildSettings`.
+async fn settings(ctx: &Context, store: &Store, cmd: &CommandInteraction) {
+    let Some(guild_id) = cmd.guild_id.filter(|_| requester(cmd).admin) else {
+        reply_ephemeral(ctx, cmd, "Sorry! Only admins can change CalBot's settings.").await;
+        return;
+    };
+    let mut settings = match store.settings(guild_id) {
+        Ok(settings) => settings,
+        Err(why) => {
+            println!("Error reading settings: {why}");
+            reply_ephemeral(ctx, cmd, "Sorry! Something went wrong.").await;
+            return;
+        }
+    };
+    let options = cmd.data.options();
+    for option in &options {
+        match (option.name, &option.value) {
+            ("discord_events", ResolvedValue::Boolean(b)) => settings.discord_events = *b,
+            ("import_events", ResolvedValue::Boolean(true)) => {
+                settings.import_channel = settings.import_channel.or(Some(cmd.channel_id));
+            }
+            ("import_events", ResolvedValue::Boolean(false)) => settings.import_channel = None,
+            ("reaction_emoji", ResolvedValue::String(emoji)) => {
+                let Some(emoji) = reactions::parse_emoji(emoji) else {
+                    reply_ephemeral(
+                        ctx,
+                        cmd,
+                        format!("Sorry! `{emoji}` isn't an emoji, try something like 📅."),
+                    )
+                    .await;
+                    return;
+                };
+                settings.reaction_emoji = (emoji != reactions::DEFAULT_EMOJI).then_some(emoji);
+            }
+            _ => {}
+        }
+    }
+    // after import_events, so picking a channel turns importing on or moves it
+    for option in &options {
+        if let ("import_channel", ResolvedValue::Channel(channel)) = (option.name, &option.value) {
+            settings.import_channel = Some(channel.id);
+        }
+    }
+    if !options.is_empty() {
+        if let Err(why) = store.save_settings(guild_id, &settings) {
+            println!("Error saving settings: {why}");
+            reply_ephemeral(ctx, cmd, "Sorry! I couldn't save that.").await;
+            return;
+        }
+    }
+    reply_ephemeral(ctx, cmd, settings_list(&settings)).await;
+}
+
+fn settings_list(settings: &GuildSettings) -> String {
+    let on = |b: bool| if b { "on" } else { "off" };
+    let import = match settings.import_channel {
+        Some(channel) => format!("posted in {}", channel.mention()),
+        None => "off".to_owned(),
+    };
+    format!(
+        "CalBot's settings in this server:\n- Discord events: {}\n- Importing Discord events: \
+         {import}\n- Reaction emoji: {}",
+        on(settings.discord_events),
+        settings.reaction_emoji()
+    )
+}
+
+/// Starts, stops or lists the guild's announcement channels.
+async fn watch(ctx: &Context, store: &Store, cmd: &CommandInteraction) {
+    let Some(guild_id) = cmd.guild_id.filter(|_| requester(cmd).admin) else {
+        reply_ephemeral(
+            ctx,
+            cmd,
+            "Sorry! Only admins can change which channels CalBot watches.",
+        )
+        .await;
+        return;
+    };
+    let Some(ResolvedOption {
+        name,
+        value: ResolvedValue::SubCommand(args),
+        ..
+    }) = cmd.data.options().into_iter().next()
+    else {
+        return;
+    };
+
+    let reply = match args.first().map(|arg| &arg.value) {
+        Some(ResolvedValue::Channel(channel)) => {
+            let on = name == "on";
+            let mention = channel.id.mention();
+            match store.watch(guild_id, channel.id, on) {
+                Ok(false) => "Nothing to change.".to_owned(),
+                Ok(true) if on => format!(
+                    "CalBot will now reply to events posted in {mention}. Put `{}` in a message \
+                     to skip it.",
+                    watch::OPT_OUT
+                ),
+                Ok(true) => format!("CalBot no longer watches {mention}."),
+                Err(why) => {
+                    println!("Error saving watched channel: {why}");
+                    "Sorry! I couldn't save that.".to_owned()
+                }
+            }
+        }
+        _ => match store.watched_channels(guild_id) {
+            Ok(channels) if channels.is_empty() => "CalBot doesn't watch any channels.".to_owned(),
+            Ok(channels) => {
+                let channels: Vec<_> = channels.iter().map(|id| id.mention().to_string()).collect();
+                format!("CalBot watches {}.", channels.join(", "))
+            }
+            Err(why) => {
+                println!("Error reading watched channels: {why}");
+                "Sorry! Something went wrong.".to_owned()
+            }
+        },
+    };
+    reply_ephemeral(ctx, cmd, reply).await;
+}
@@ -0,0 +1,131 @@
+//! The encoding the LLM uses to write dates, decoded by `parser::parse_date`.
+//!
+//! The prompt's "Date Formats" section is rendered from [`DATE_FORMS`], and the examples shown
+//! to the model are run through `parse_date` by the parser tests, so the instructions and the
+//! decoder can't disagree. Bump [`DATE_GRAMMAR_VERSION`] whenever the encoding changes.
+
+/// v1 asked for hexadecimal day offsets while `parse_date` read them as decimal.
+/// v2 uses decimal everywhere.
+pub const DATE_GRAMMAR_VERSION: u32 = 2;
+
+pub struct DateForm {
+    pub name: &'static str,
+    pub prefix: char,
+    pub description: &'static str,
+    pub examples: &'static [DateExample],
+}
+
+pub struct DateExample {
+    /// How the date is written in a message
+    pub phrase: &'static str,
+    pub encoded: &'static str,
+    /// Date the message was sent, as YYYY-MM-DD
+    pub message_date: &'static str,
+    /// Date `encoded` resolves to, as YYYY-MM-DD
+    pub resolved: &'static str,
+}
+
+/// Every date form, from most to least preferred.
+pub const DATE_FORMS: &[DateForm] = &[
+    DateForm {
+        name: "Relative Dates",
+        prefix: '+',
+        description: "\"+N\" where N is the number of days after the message, written in \
+            DECIMAL. When you encounter tomorrow, use \"+1\" not \"+0\".",
+        examples: &[
+            DateExample {
+                phrase: "today",
+                encoded: "+0",
+                message_date: "2025-02-03",
+                resolved: "2025-02-03",
+            },
+            DateExample {
+                phrase: "tomorrow",
+                encoded: "+1",
+                message_date: "2025-02-03",
+                resolved: "2025-02-04",
+            },
+            DateExample {
+                phrase: "in 12 days",
+                encoded: "+12",
+                message_date: "2025-02-03",
+                resolved: "2025-02-15",
+            },
+            DateExample {
+                phrase: "in two weeks",
+                encoded: "+14",
+                message_date: "2025-02-20",
+                resolved: "2025-03-06",
+            },
+        ],
+    },
+    DateForm {
+        name: "Exact Dates",
+        prefix: 'x',
+        description: "\"xMMDD\", or \"xYYYYMMDD\" if the message states the year.",
+        examples: &[
+            DateExample {
+                phrase: "9/21",
+                encoded: "x0921",
+                message_date: "2025-02-03",
+                resolved: "2025-09-21",
+            },
+            DateExample {
+                phrase: "January 15th",
+                encoded: "x0115",
+                message_date: "2024-12-20",
+                resolved: "2025-01-15",
+            },
+            DateExample {
+                phrase: "9/21/2026",
+                encoded: "x20260921",
+                message_date: "2025-02-03",
+                resolved: "2026-09-21",
+            },
+        ],
+    },
+    DateForm {
+        name: "Relative Weekdays",
+        prefix: '_',
+        description: "\"_\" followed by the first three letters of the weekday, for the next \
+            time that weekday comes around.",
+        examples: &[
+            DateExample {
+                phrase: "next Monday",
+                encoded: "_mon",
+                message_date: "2025-02-03",
+                resolved: "2025-02-10",
+            },
+            DateExample {
+                phrase: "this Thursday",
+                encoded: "_thu",
+                message_date: "2025-02-03",
+                resolved: "2025-02-06",
+            },
+        ],
+    },
+];
+
+/// The "Date Formats" section of the prompt.
+pub fn prompt_section() -> String {
+    let mut section = format!("# Date Formats (version {DATE_GRAMMAR_VERSION})\n");
+    for form in DATE_FORMS {
+        section.push_str(&format!(
+            "{} (start with \"{}\"): {}\n",
+            form.name, form.prefix, form.description
+        ));
+        for example in form.examples {
+            section.push_str(&format!(
+                "  - \"{}\" in a message sent on {} is \"{}\" ({})\n",
+                example.phrase, example.message_date, example.encoded, example.resolved
+            ));
+        }
+    }
+
+    let names: Vec<_> = DATE_FORMS.iter().map(|form| form.name).collect();
+    section.push_str(&format!(
+        "\nIf there are a combination of date formats, use this order (most to least priority): {}.\n",
+        names.join(", ")
+    ));
+    section
+}
@@ -1,16 +1,437 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use icalendar::{Calendar, Component, Event, EventStatus};
 use serenity::{
     all::{
-        Context, CreateButton, CreateMessage, EventHandler, Guild, Message, MessageBuilder, Ready,
+        ChannelId, Command, Context, CreateMessage, EditMessage, EventHandler, Guild, GuildId,
+        Interaction, Message, MessageId, MessageUpdateEvent, Reaction, Ready, ScheduledEvent,
+        ScheduledEventId, ScheduledEventStatus, UserId,
     },
     async_trait,
 };
 
 use crate::{
-    parser::{parse_msg, Error},
-    utils::{calendar_message, upload_calendar},
+    access::{Access, Requester},
+    backend::ExtractionBackend,
+    commands,
+    host::IcsHost,
+    mentions::resolve_mentions,
+    parser::{detect_event, parse_msg, Error},
+    reactions,
+    review::{self, cancelled_content, draft_buttons, draft_content, Draft, Drafts},
+    scheduled,
+    store::{Converted, Origin, Store},
+    timezone::guild_timezone,
+    utils::{calendar_buttons, calendar_events, error_reply, local_date, CalendarUrls},
+    watch,
 };
 
-pub struct Handler;
+pub struct Handler {
+    /// `None` when no LLM is configured, see `backend::from_env`
+    pub backend: Option<Box<dyn ExtractionBackend>>,
+    pub drafts: Drafts,
+    pub access: Access,
+    pub store: Arc<Store>,
+    pub host: Box<dyn IcsHost>,
+}
+
+impl Handler {
+    /// Publishes the events converted from `origin`, after identifying them by it (see
+    /// `Store::identify`).
+    pub async fn publish(
+        &self,
+        ctx: &Context,
+        res: Result<Calendar, Error>,
+        origin: &Origin,
+    ) -> Result<(Calendar, CalendarUrls), Error> {
+        let mut calendar = res?;
+        if let Err(why) = self.store.identify(&mut calendar, origin) {
+            println!("Error identifying events: {why}");
+        }
+        let urls = self.host.publish(ctx, &calendar).await?;
+        Ok((calendar, urls))
+    }
+
+    /// The Discord scheduled events made for an announcement, see `Store::scheduled_events`.
+    fn scheduled_events(&self, source: MessageId) -> Vec<(String, ScheduledEventId)> {
+        self.store.scheduled_events(source).unwrap_or_else(|why| {
+            println!("Error reading scheduled events: {why}");
+            Vec::new()
+        })
+    }
+
+    /// Whether confirmed events in the guild also become Discord scheduled events.
+    pub fn discord_events(&self, guild_id: Option<GuildId>) -> bool {
+        let Some(guild_id) = guild_id else {
+            return false;
+        };
+        match self.store.settings(guild_id) {
+            Ok(settings) => settings.discord_events,
+            Err(why) => {
+                println!("Error reading settings: {why}");
+                false
+            }
+        }
+    }
+
+    /// Cancels the events of a deleted announcement, and any Discord scheduled events made for
+    /// them. Only public conversions are tracked by their announcement, so private grabs with
+    /// "Create calendar event" are left alone.
+    async fn cancel(&self, ctx: &Context, source: MessageId) {
+        let converted = match self.store.converted(source) {
+            Ok(Some(converted)) => converted,
+            Ok(None) => return,
+            Err(why) => {
+                println!("Error looking up deleted message: {why}");
+                return;
+            }
+        };
+        let events = match self.store.source_events(source) {
+            Ok(events) => events,
+            Err(why) => {
+                println!("Error reading events of deleted message: {why}");
+                return;
+            }
+        };
+
+        let previous = self.scheduled_events(source);
+        self.cancel_events(ctx, &converted, events, "the announcement was deleted")
+            .await;
+        if let Some(guild_id) = converted.origin.guild_id {
+            if let Err(why) = scheduled::cancel(ctx, guild_id, &previous).await {
+                println!("Error cancelling scheduled events of message {source}: {why}");
+            }
+        }
+    }
+
+    /// Publishes `events` again as cancelled with the same UIDs, so calendars that have them drop
+    /// them, and says why on the bot's message showing them.
+    async fn cancel_events(
+        &self,
+        ctx: &Context,
+        converted: &Converted,
+        events: Vec<Event>,
+        reason: &str,
+    ) {
+        let now = Utc::now();
+        let mut calendar = Calendar::new();
+        calendar.append_property(("METHOD", "CANCEL"));
+        for mut event in events {
+            let sequence = event.get_sequence().unwrap_or_default() + 1;
+            event
+                .status(EventStatus::Cancelled)
+                .sequence(sequence)
+                .timestamp(now);
+            calendar.push(event);
+        }
+        let calendar = calendar.done();
+        let cal_urls = match self.host.publish(ctx, &calendar).await {
+            Ok(urls) => urls,
+            Err(e) => {
+                println!(
+                    "Error cancelling events of message {}: {e}",
+                    converted.reply
+                );
+                return;
+            }
+        };
+
+        let events: Vec<_> = calendar_events(&calendar).into_iter().cloned().collect();
+        if let Err(why) = self
+            .store
+            .record(&converted.origin, converted.reply, &events, &cal_urls)
+        {
+            println!("Error recording events: {why}");
+        }
+        let edit = EditMessage::new()
+            .content(cancelled_content(&calendar, reason))
+            .components(Vec::new());
+        let channel = converted.origin.channel_id;
+        if let Err(why) = channel.edit_message(ctx, converted.reply, edit).await {
+            println!("Error editing reply: {why}");
+        }
+    }
+
+    /// Whether `user` is allowed to use the bot where `msg` is, see `access`.
+    async fn allowed(&self, ctx: &Context, msg: &Message, user: UserId) -> bool {
+        let Some(guild_id) = msg.guild_id else {
+            return false;
+        };
+        let guild = match Guild::get(ctx, guild_id).await {
+            Ok(guild) => guild,
+            Err(why) => {
+                println!("Error fetching guild: {why}");
+                return false;
+            }
+        };
+        let member = match guild.member(ctx, user).await {
+            Ok(member) => member,
+            Err(why) => {
+                println!("Error fetching member: {why}");
+                return false;
+            }
+        };
+        let Some(channel) = msg.channel(ctx).await.ok().and_then(|c| c.guild()) else {
+            println!("Error fetching channel {}", msg.channel_id);
+            return false;
+        };
+        let perms = guild.user_permissions_in(&channel, &member);
+        let requester = Requester {
+            user,
+            roles: &member.roles,
+            channel: channel.id,
+            parent: channel.parent_id,
+            admin: perms.administrator(),
+        };
+        self.access.get(guild.id).allows(&requester)
+    }
+
+    /// Converts `source` for `author`, replying to `reply_to` with a draft of its events (see
+    /// `review`), or why there aren't any.
+    async fn convert(&self, ctx: &Context, source: &Message, author: UserId, reply_to: &Message) {
+        let tz = guild_timezone(source.guild_id);
+        // relative dates like "tomorrow" mean the day it was posted, even if it was edited since
+        let anchor = source.timestamp;
+        let content = resolve_mentions(ctx, source.guild_id, &source.content, &source.mentions);
+        let anchor_date = local_date(*anchor, tz);
+        let res = parse_msg(self.backend.as_deref(), &content, &anchor_date, tz).await;
+        let origin = Origin {
+            guild_id: reply_to.guild_id,
+            channel_id: reply_to.channel_id,
+            source: Some(source.id),
+            author,
+            private: false,
+        };
+
+        match self.publish(ctx, res, &origin).await {
+            Ok((calendar, cal_urls)) => {
+                let discord_events = self.discord_events(origin.guild_id);
+                let draft = Draft::new(&calendar, cal_urls, origin, discord_events);
+
+                let message = CreateMessage::new()
+                    .content(draft_content(&calendar))
+                    .reference_message(reply_to)
+                    .components(draft_buttons(&draft.events));
+                match reply_to.channel_id.send_message(ctx, message).await {
+                    Ok(sent) => self.drafts.insert(sent.id, draft),
+                    Err(why) => println!("Error sending message: {why}"),
+                }
+            }
+            Err(e) => {
+                let Some(reply) = error_reply(&e) else {
+                    return;
+                };
+                if let Err(why) = reply_to.reply(ctx, reply).await {
+                    println!("Error sending message: {why}");
+                }
+            }
+        }
+    }
+
+    /// Converts a message someone reacted to, unless it's already been converted. The message
+    /// must be claimed in `drafts` first, see `reaction_add`.
+    async fn convert_reacted(&self, ctx: &Context, reaction: &Reaction, user_id: UserId) {
+        match self.store.converted(reaction.message_id) {
+            Ok(None) => {}
+            Ok(Some(_)) => return,
+            Err(why) => {
+                println!("Error looking up reacted message: {why}");
+                return;
+            }
+        }
+
+        let msg = match reaction.message(ctx).await {
+            Ok(msg) => msg,
+            Err(why) => {
+                println!("Error fetching reacted message: {why}");
+                return;
+            }
+        };
+        if msg.author.id == ctx.cache.current_user().id {
+            return;
+        }
+        // reacting is too easy to do by accident to answer with a refusal
+        if !self.allowed(ctx, &msg, user_id).await {
+            return;
+        }
+        self.convert(ctx, &msg, user_id, &msg).await;
+    }
+
+    /// Converts a message in an announcement channel if it looks like an event, replying with its
+    /// "Add to iCal" buttons, see `watch`. There's no draft to confirm, since nobody asked.
+    async fn watch(&self, ctx: &Context, msg: &Message) {
+        let Some(guild_id) = msg.guild_id else {
+            return;
+        };
+        match self.store.is_watched(msg.channel_id) {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(why) => {
+                println!("Error looking up watched channel: {why}");
+                return;
+            }
+        }
+        if watch::opted_out(&msg.content) {
+            return;
+        }
+
+        let tz = guild_timezone(msg.guild_id);
+        let content = resolve_mentions(ctx, msg.guild_id, &msg.content, &msg.mentions);
+        let anchor_date = local_date(*msg.timestamp, tz);
+        let res = detect_event(self.backend.as_deref(), &content, &anchor_date, tz).await;
+        let origin = Origin {
+            guild_id: msg.guild_id,
+            channel_id: msg.channel_id,
+            source: Some(msg.id),
+            author: msg.author.id,
+            private: false,
+        };
+        let (calendar, cal_urls) = match self.publish(ctx, res, &origin).await {
+            Ok(published) => published,
+            Err(Error::ParseFailure) => return,
+            Err(e) => {
+                println!(
+                    "Error converting message {} in a watched channel: {e}",
+                    msg.id
+                );
+                return;
+            }
+        };
+
+        let message = CreateMessage::new()
+            .content(draft_content(&calendar))
+            .reference_message(msg)
+            .components(calendar_buttons(&calendar, cal_urls.clone()));
+        let reply = match msg.channel_id.send_message(ctx, message).await {
+            Ok(sent) => sent.id,
+            Err(why) => {
+                println!("Error sending message: {why}");
+                return;
+            }
+        };
+        let events: Vec<_> = calendar_events(&calendar).into_iter().cloned().collect();
+        if let Err(why) = self.store.record(&origin, reply, &events, &cal_urls) {
+            println!("Error recording events: {why}");
+        }
+        if self.discord_events(msg.guild_id) {
+            if let Err(why) = scheduled::sync(ctx, &self.store, guild_id, &events, &[]).await {
+                println!("Error creating scheduled events: {why}");
+            }
+        }
+    }
+
+    /// Posts a scheduled event made in Discord to the guild's import channel as a calendar
+    /// event, or updates the post if it was imported before. Nothing is imported until an admin
+    /// picks the channel with `/settings`.
+    async fn import(&self, ctx: &Context, scheduled: &ScheduledEvent) {
+        let bot_id = ctx.cache.current_user().id;
+        if scheduled.creator_id == Some(bot_id) {
+            // made by `scheduled::sync` from an announcement, which already has its events
+            return;
+        }
+        match scheduled.status {
+            ScheduledEventStatus::Scheduled => {}
+            ScheduledEventStatus::Canceled => return self.cancel_import(ctx, scheduled.id).await,
+            // starting or ending doesn't change the event
+            _ => return,
+        }
+
+        let previous = match self.store.imported(scheduled.id) {
+            Ok(previous) => previous,
+            Err(why) => {
+                println!("Error looking up scheduled event: {why}");
+                return;
+            }
+        };
+        let (origin, reply, sequence) = match previous {
+            Some((converted, _)) => (
+                converted.origin,
+                Some(converted.reply),
+                converted.sequence + 1,
+            ),
+            None => {
+                let channel = match self.store.settings(scheduled.guild_id) {
+                    Ok(settings) => settings.import_channel,
+                    Err(why) => {
+                        println!("Error reading settings: {why}");
+                        None
+                    }
+                };
+                let Some(channel_id) = channel else {
+                    return;
+                };
+                let origin = Origin {
+                    guild_id: Some(scheduled.guild_id),
+                    channel_id,
+                    source: None,
+                    author: scheduled.creator_id.unwrap_or(bot_id),
+                    private: false,
+                };
+                (origin, None, 0)
+            }
+        };
+
+        let mut event = scheduled::imported_event(ctx, scheduled);
+        event.sequence(sequence).timestamp(Utc::now());
+        let calendar = Calendar::new().push(event.clone()).done();
+        let cal_urls = match self.host.publish(ctx, &calendar).await {
+            Ok(urls) => urls,
+            Err(e) => {
+                println!("Error importing scheduled event {}: {e}", scheduled.id);
+                return;
+            }
+        };
+
+        let content = draft_content(&calendar);
+        let components = calendar_buttons(&calendar, cal_urls.clone());
+        let channel = origin.channel_id;
+        let sent = match reply {
+            Some(reply) => {
+                let edit = EditMessage::new().content(content).components(components);
+                channel.edit_message(ctx, reply, edit).await
+            }
+            None => {
+                let message = CreateMessage::new().content(content).components(components);
+                channel.send_message(ctx, message).await
+            }
+        };
+        let reply = match sent {
+            Ok(sent) => sent.id,
+            Err(why) => {
+                println!("Error posting scheduled event: {why}");
+                return;
+            }
+        };
+        let recorded = self
+            .store
+            .record(&origin, reply, &[event.clone()], &cal_urls)
+            .and_then(|()| {
+                let uid = event.get_uid().unwrap_or_default();
+                self.store.set_scheduled_event(uid, scheduled.id)
+            });
+        if let Err(why) = recorded {
+            println!("Error recording events: {why}");
+        }
+    }
+
+    /// Cancels the event a scheduled event was imported as, if it was.
+    async fn cancel_import(&self, ctx: &Context, id: ScheduledEventId) {
+        match self.store.imported(id) {
+            Ok(Some((converted, event))) => {
+                self.cancel_events(
+                    ctx,
+                    &converted,
+                    vec![event],
+                    "the Discord event was cancelled",
+                )
+                .await
+            }
+            Ok(None) => {}
+            Err(why) => println!("Error looking up scheduled event: {why}"),
+        }
+    }
+}
 
 #[async_trait]
 impl EventHandler for Handler {
@@ -25,25 +446,16 @@ impl EventHandler for Handler {
             return;
         }
 
-        if !msg.mentions_user_id(&bot_id) {
-            // bot only responds to @CalBot mentions
+        if !msg.mentions_user_id(bot_id) {
+            // bot only responds to @CalBot mentions, except in announcement channels
+            self.watch(&ctx, &msg).await;
             return;
         }
 
-        // check that user is an admin
-        let guild = Guild::get(&ctx, msg.guild_id.unwrap())
-            .await
-            .expect("msg should have guild");
-        let member = guild
-            .member(&ctx, msg.author.id)
-            .await
-            .expect("msg should have came from member in guild");
-        let perms = guild.member_permissions(&member);
-
-        if !perms.administrator() {
+        if !self.allowed(&ctx, &msg, msg.author.id).await {
             if let Err(why) = msg
                 .channel_id
-                .say(&ctx, "Sorry! Only admins can use this bot.")
+                .say(&ctx, "Sorry! You're not allowed to use this bot here.")
                 .await
             {
                 println!("Error sending message: {why}");
@@ -54,53 +466,149 @@ impl EventHandler for Handler {
         // The bot accepts two inputs
         // 1. A message with information with mentions it with an @CalBot
         // 2. Replying to a message with information and mentioning @CalBot in the reply
-        let res = match msg.referenced_message {
-            Some(ref ref_msg) => {
-                if let Some(edited) = ref_msg.edited_timestamp {
-                    parse_msg(&ref_msg.content, &edited.date_naive()).await
-                } else {
-                    parse_msg(&ref_msg.content, &ref_msg.timestamp.date_naive()).await
-                }
+        let source = msg.referenced_message.as_deref().unwrap_or(&msg);
+        self.convert(&ctx, source, msg.author.id, &msg).await;
+    }
+
+    // Reacting to a message with the guild's emoji converts it like replying to it with a mention
+    // would, see `reactions`.
+    async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
+        let (Some(guild_id), Some(user_id)) = (reaction.guild_id, reaction.user_id) else {
+            return;
+        };
+        if user_id == ctx.cache.current_user().id {
+            return;
+        }
+        let emoji_matches = match self.store.settings(guild_id) {
+            Ok(settings) => reactions::matches(settings.reaction_emoji(), &reaction.emoji),
+            Err(why) => {
+                println!("Error reading settings: {why}");
+                false
             }
-            None => parse_msg(&msg.content, &msg.timestamp.date_naive()).await,
         };
+        if !emoji_matches {
+            return;
+        }
+        // only the first reaction does anything
+        if !self.drafts.claim(reaction.message_id) {
+            return;
+        }
+        self.convert_reacted(&ctx, &reaction, user_id).await;
+        self.drafts.release(reaction.message_id);
+    }
 
-        match res {
-            Ok(calendar) => {
-                let cal_url = upload_calendar(&ctx, &calendar).await;
-                let btn = CreateButton::new_link(cal_url).label("Add to iCal");
-
-                let mut cal_msg = MessageBuilder::new();
-                calendar_message(&calendar, &mut cal_msg);
+    // Announcements the bot already converted are converted again when they're edited, updating
+    // the events (see `Store::identify`), the bot's reply and any Discord scheduled events.
+    async fn message_update(
+        &self,
+        ctx: Context,
+        _old_if_available: Option<Message>,
+        _new: Option<Message>,
+        event: MessageUpdateEvent,
+    ) {
+        let Some(content) = event.content else {
+            // only the text matters
+            return;
+        };
+        let converted = match self.store.converted(event.id) {
+            Ok(Some(converted)) => converted,
+            Ok(None) => return,
+            Err(why) => {
+                println!("Error looking up edited message: {why}");
+                return;
+            }
+        };
 
-                let message = CreateMessage::new()
-                    .content(cal_msg.build())
-                    .button(btn)
-                    .reference_message(&msg);
-                if let Err(why) = msg.channel_id.send_message(&ctx, message).await {
-                    println!("Error sending message: {why}");
-                }
+        let tz = guild_timezone(event.guild_id);
+        // the same anchor the events were first converted with, see `convert`
+        let anchor = event.timestamp.unwrap_or_else(|| event.id.created_at());
+        let mentions = event.mentions.unwrap_or_default();
+        let content = resolve_mentions(&ctx, event.guild_id, &content, &mentions);
+        let anchor_date = local_date(*anchor, tz);
+        let res = parse_msg(self.backend.as_deref(), &content, &anchor_date, tz).await;
+        let (calendar, cal_urls) = match self.publish(&ctx, res, &converted.origin).await {
+            Ok(published) => published,
+            Err(e) => {
+                println!("Error converting edited message {}: {e}", event.id);
                 return;
             }
-            Err(Error::ParseFailure) => {
-                if let Err(why) = msg
-                    .reply(&ctx, "Sorry! I couldn't parse that message.")
-                    .await
-                {
-                    println!("Error sending message: {why}");
-                }
+        };
+
+        let events: Vec<_> = calendar_events(&calendar).into_iter().cloned().collect();
+        let previous = self.scheduled_events(event.id);
+        if let Err(why) = self
+            .store
+            .record(&converted.origin, converted.reply, &events, &cal_urls)
+        {
+            println!("Error recording events: {why}");
+        }
+        if let (Some(guild_id), false) = (converted.origin.guild_id, previous.is_empty()) {
+            if let Err(why) = scheduled::sync(&ctx, &self.store, guild_id, &events, &previous).await
+            {
+                println!(
+                    "Error updating scheduled events of message {}: {why}",
+                    event.id
+                );
             }
-            Err(Error::NoResponse) => {
-                if let Err(why) = msg
-                    .reply(&ctx.http, "Sorry! The LLM didn't respond. Try again later.")
-                    .await
-                {
-                    println!("Error sending message: {why}");
-                }
+        }
+        let edit = EditMessage::new()
+            .content(draft_content(&calendar))
+            .components(calendar_buttons(&calendar, cal_urls));
+        let channel = converted.origin.channel_id;
+        if let Err(why) = channel.edit_message(&ctx, converted.reply, edit).await {
+            println!("Error editing reply: {why}");
+        }
+    }
+
+    // Announcements the bot already converted cancel their events when they're deleted.
+    async fn message_delete(
+        &self,
+        ctx: Context,
+        _channel_id: ChannelId,
+        deleted_message_id: MessageId,
+        _guild_id: Option<GuildId>,
+    ) {
+        self.cancel(&ctx, deleted_message_id).await;
+    }
+
+    async fn message_delete_bulk(
+        &self,
+        ctx: Context,
+        _channel_id: ChannelId,
+        multiple_deleted_messages_ids: Vec<MessageId>,
+        _guild_id: Option<GuildId>,
+    ) {
+        for id in multiple_deleted_messages_ids {
+            self.cancel(&ctx, id).await;
+        }
+    }
+
+    // Scheduled events made in Discord are imported as calendar events, see `Handler::import`.
+    async fn guild_scheduled_event_create(&self, ctx: Context, event: ScheduledEvent) {
+        self.import(&ctx, &event).await;
+    }
+
+    async fn guild_scheduled_event_update(&self, ctx: Context, event: ScheduledEvent) {
+        self.import(&ctx, &event).await;
+    }
+
+    async fn guild_scheduled_event_delete(&self, ctx: Context, event: ScheduledEvent) {
+        self.cancel_import(&ctx, event.id).await;
+    }
+
+    // Application commands (see `commands`), and the buttons and forms of drafts (see `review`).
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        match interaction {
+            Interaction::Command(cmd) => {
+                commands::run(&ctx, self, &cmd).await;
+            }
+            Interaction::Component(comp) => {
+                review::button(&ctx, &self.drafts, &self.store, &comp).await;
             }
-            Err(Error::Reqwest(e)) => {
-                println!("Error: {e}");
+            Interaction::Modal(modal) => {
+                review::submit(&ctx, &self.drafts, self.host.as_ref(), &modal).await;
             }
+            _ => {}
         }
     }
 
@@ -108,8 +616,12 @@ impl EventHandler for Handler {
     // a READY payload is sent by Discord. This payload contains data like the current user's guild
     // Ids, current user data, private channels, and more.
     //
-    // In this case, just print what the current user's username is.
-    async fn ready(&self, _: Context, ready: Ready) {
+    // In this case, print what the current user's username is and register the slash commands.
+    async fn ready(&self, ctx: Context, ready: Ready) {
         println!("{} is connected!", ready.user.name);
+
+        if let Err(why) = Command::set_global_commands(&ctx, commands::all()).await {
+            println!("Error registering commands: {why}");
+        }
     }
 }
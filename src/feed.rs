@@ -0,0 +1,90 @@
+//! Subscribable calendars of every event confirmed in a guild, or in one of its channels.
+//!
+//! They're served at `<CALBOT_PUBLIC_URL>/feed/<token>.ics`, see `http`. The token is the only
+//! thing protecting a feed, so it's random and can be reset with `/feed reset:True`.
+
+use chrono::Duration;
+use icalendar::Calendar;
+
+use crate::{
+    store::{self, Store},
+    timezone,
+};
+
+/// The https:// and webcal:// links of a feed.
+pub fn feed_urls(public_url: &str, token: &str) -> (String, String) {
+    let https = format!("{public_url}/feed/{token}.ics");
+    let webcal = match https.split_once("://") {
+        Some((_, rest)) => format!("webcal://{rest}"),
+        None => format!("webcal://{https}"),
+    };
+    (https, webcal)
+}
+
+/// The feed with `token` as an .ics file.
+pub fn feed_ics(store: &Store, token: &str) -> rusqlite::Result<Option<String>> {
+    let Some(feed) = store.feed(token)? else {
+        return Ok(None);
+    };
+    let events = store.feed_events(&feed)?;
+    Ok(Some(timezone::to_ics(&feed_calendar(&feed.name, &events))))
+}
+
+/// A calendar of stored VEVENTs, which calendar apps check for new events every hour.
+fn feed_calendar(name: &str, events: &[String]) -> Calendar {
+    let mut calendar = Calendar::new();
+    calendar.name(name).ttl(&Duration::hours(1));
+    for event in events.iter().filter_map(|ics| store::parse_event(ics)) {
+        calendar.push(event);
+    }
+    calendar.done()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use icalendar::{CalendarDateTime, Component, Event, EventLike};
+
+    use super::*;
+
+    #[test]
+    fn urls() {
+        assert_eq!(
+            feed_urls("https://calbot.example.com", "abc"),
+            (
+                "https://calbot.example.com/feed/abc.ics".to_owned(),
+                "webcal://calbot.example.com/feed/abc.ics".to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn calendar_from_stored_events() {
+        let start = NaiveDate::from_ymd_opt(2025, 9, 21)
+            .unwrap()
+            .and_hms_opt(16, 0, 0)
+            .unwrap();
+        let event = Event::new()
+            .uid("a")
+            .summary("ACM General Meeting")
+            .starts(CalendarDateTime::WithTimezone {
+                date_time: start,
+                tzid: "America/Los_Angeles".to_owned(),
+            })
+            .add_property("RRULE", "FREQ=WEEKLY")
+            .done();
+        let calendar = feed_calendar("ACM", &[event.to_string(), "garbage".to_owned()]);
+        assert_eq!(calendar.get_name(), Some("ACM"));
+
+        let events: Vec<_> = calendar
+            .components
+            .iter()
+            .filter_map(|c| c.as_event())
+            .collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].get_uid(), Some("a"));
+        assert_eq!(events[0].get_start(), event.get_start());
+        assert_eq!(events[0].property_value("RRULE"), Some("FREQ=WEEKLY"));
+        assert!(timezone::to_ics(&calendar).contains("BEGIN:VTIMEZONE"));
+    }
+}
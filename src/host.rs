@@ -0,0 +1,175 @@
+//! Where .ics files are published for the "Add to iCal" buttons to link to.
+
+use std::{env, sync::Arc};
+
+use icalendar::{Calendar, Component, Event};
+use serenity::{
+    all::{ChannelId, Context, CreateAttachment, CreateMessage},
+    async_trait,
+};
+
+use crate::{
+    http,
+    parser::Error,
+    store::Store,
+    timezone,
+    utils::{calendar_events, CalendarUrls},
+};
+
+/// Publishes calendars somewhere members can download them from.
+#[async_trait]
+pub trait IcsHost: Send + Sync {
+    async fn publish(&self, ctx: &Context, calendar: &Calendar) -> Result<CalendarUrls, Error>;
+}
+
+/// An .ics file to publish, and what it holds: the UIDs of its events, comma separated.
+struct IcsFile {
+    key: String,
+    ics: String,
+}
+
+/// A single file with every event, then one per event when there are several.
+fn ics_files(calendar: &Calendar) -> Vec<IcsFile> {
+    let events = calendar_events(calendar);
+    let key = |events: &[&Event]| {
+        let uids: Vec<_> = events
+            .iter()
+            .map(|e| e.get_uid().unwrap_or_default())
+            .collect();
+        uids.join(",")
+    };
+    let mut files = vec![IcsFile {
+        key: key(&events),
+        ics: timezone::to_ics(calendar),
+    }];
+    if events.len() > 1 {
+        for event in &events {
+            let single = Calendar::new().push((*event).clone()).done();
+            files.push(IcsFile {
+                key: key(&[event]),
+                ics: timezone::to_ics(&single),
+            });
+        }
+    }
+    files
+}
+
+/// Uploads the files as attachments to a private channel and links to them on Discord's CDN.
+///
+/// Discord signs attachment URLs, so the links stop working after a while.
+pub struct DiscordChannel {
+    channel: ChannelId,
+}
+
+#[async_trait]
+impl IcsHost for DiscordChannel {
+    async fn publish(&self, ctx: &Context, calendar: &Calendar) -> Result<CalendarUrls, Error> {
+        let mut message = CreateMessage::new();
+        for (i, file) in ics_files(calendar).into_iter().enumerate() {
+            let name = match i {
+                0 => "CalBot.ics".to_owned(),
+                i => format!("CalBot-{i}.ics"),
+            };
+            message = message.add_file(CreateAttachment::bytes(file.ics.into_bytes(), name));
+        }
+
+        let sent = self
+            .channel
+            .send_message(ctx, message)
+            .await
+            .map_err(|e| Error::Publish(e.to_string()))?;
+        let mut urls = sent.attachments.into_iter().map(|a| a.url);
+        Ok(CalendarUrls {
+            all: urls
+                .next()
+                .ok_or_else(|| Error::Publish("no attachment on the message".to_owned()))?,
+            events: urls.collect(),
+        })
+    }
+}
+
+/// Serves the files from the bot's own HTTP server, see `http`.
+///
+/// Every file gets a random ID that stays the same as long as it holds the same events, so an
+/// edited event is still at the link the old one was.
+pub struct Http {
+    store: Arc<Store>,
+    public_url: String,
+}
+
+#[async_trait]
+impl IcsHost for Http {
+    async fn publish(&self, _ctx: &Context, calendar: &Calendar) -> Result<CalendarUrls, Error> {
+        let mut urls = ics_files(calendar).into_iter().map(|file| {
+            let id = self
+                .store
+                .put_ics(&file.key, &file.ics)
+                .map_err(|e| Error::Publish(e.to_string()))?;
+            Ok(http::ics_url(&self.public_url, &id))
+        });
+        let all = urls
+            .next()
+            .expect("there's always a file with every event")?;
+        Ok(CalendarUrls {
+            all,
+            events: urls.collect::<Result<_, Error>>()?,
+        })
+    }
+}
+
+/// Picks the host from `CALBOT_ICS_HOST`: `http`, or `discord` to upload to `CALBOT_CHAN`. It
+/// defaults to `http` when `CALBOT_PUBLIC_URL` is set.
+pub fn from_env(store: Arc<Store>) -> Box<dyn IcsHost> {
+    let public_url = http::public_url();
+    let kind = env::var("CALBOT_ICS_HOST").unwrap_or_else(|_| {
+        match public_url {
+            Some(_) => "http",
+            None => "discord",
+        }
+        .to_owned()
+    });
+
+    match kind.to_lowercase().as_str() {
+        "http" => Box::new(Http {
+            store,
+            public_url: public_url.expect("CALBOT_PUBLIC_URL missing"),
+        }),
+        "discord" => Box::new(DiscordChannel {
+            channel: ChannelId::new(
+                env::var("CALBOT_CHAN")
+                    .expect("CALBOT_CHAN missing")
+                    .parse()
+                    .expect("Invalid CALBOT_CHAN"),
+            ),
+        }),
+        other => panic!("Unknown CALBOT_ICS_HOST: {other}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use icalendar::EventLike;
+
+    use super::*;
+
+    #[test]
+    fn files() {
+        let event = |uid: &str| {
+            Event::new()
+                .uid(uid)
+                .summary(uid)
+                .all_day(chrono::NaiveDate::from_ymd_opt(2025, 9, 21).unwrap())
+                .done()
+        };
+        let single = Calendar::new().push(event("a")).done();
+        let keys: Vec<_> = ics_files(&single).into_iter().map(|f| f.key).collect();
+        assert_eq!(keys, ["a"]);
+
+        let both = Calendar::new().push(event("a")).push(event("b")).done();
+        let files = ics_files(&both);
+        let keys: Vec<_> = files.iter().map(|f| f.key.as_str()).collect();
+        assert_eq!(keys, ["a,b", "a", "b"]);
+        assert!(files[0].ics.contains("SUMMARY:a") && files[0].ics.contains("SUMMARY:b"));
+        assert!(!files[2].ics.contains("SUMMARY:a"));
+    }
+}
@@ -0,0 +1,71 @@
+//! The bot's HTTP server, listening on `CALBOT_HTTP_ADDR` and reachable at `CALBOT_PUBLIC_URL`.
+//!
+//! It serves calendar feeds at `/feed/<token>.ics` (see `feed`) and, with the `http` host, the
+//! .ics files of each announcement at `/ics/<id>.ics` (see `host`).
+
+use std::{env, sync::Arc};
+
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+
+use crate::{feed, store::Store};
+
+/// Where the server is reachable from, e.g. `https://calbot.example.com`. It isn't started
+/// without it.
+pub fn public_url() -> Option<String> {
+    let url = env::var("CALBOT_PUBLIC_URL").ok()?;
+    Some(url.trim().trim_end_matches('/').to_owned()).filter(|url| !url.is_empty())
+}
+
+pub fn ics_url(public_url: &str, id: &str) -> String {
+    format!("{public_url}/ics/{id}.ics")
+}
+
+/// Serves until the bot stops.
+pub async fn serve(store: Arc<Store>) {
+    let addr = env::var("CALBOT_HTTP_ADDR").unwrap_or_else(|_| "0.0.0.0:8000".to_owned());
+    let listener = match tokio::net::TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(why) => {
+            println!("Error listening on {addr}: {why}");
+            return;
+        }
+    };
+    let app = Router::new()
+        .route("/feed/:file", get(feed))
+        .route("/ics/:file", get(ics))
+        .with_state(store);
+    if let Err(why) = axum::serve(listener, app).await {
+        println!("Error serving HTTP: {why}");
+    }
+}
+
+async fn feed(State(store): State<Arc<Store>>, Path(file): Path<String>) -> Response {
+    let token = file.strip_suffix(".ics").unwrap_or(&file);
+    calendar_response(feed::feed_ics(&store, token))
+}
+
+async fn ics(State(store): State<Arc<Store>>, Path(file): Path<String>) -> Response {
+    let id = file.strip_suffix(".ics").unwrap_or(&file);
+    calendar_response(store.ics_file(id))
+}
+
+fn calendar_response(ics: rusqlite::Result<Option<String>>) -> Response {
+    match ics {
+        Ok(Some(ics)) => (
+            [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+            ics,
+        )
+            .into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(why) => {
+            println!("Error reading calendar: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
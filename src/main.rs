@@ -1,6 +1,25 @@
+mod access;
+mod backend;
+mod commands;
+mod date_grammar;
 mod events;
+mod feed;
+mod host;
+mod http;
+mod mentions;
 mod parser;
+mod reactions;
+mod recurrence;
+mod review;
+mod rules;
+mod scheduled;
+mod store;
+mod timestamps;
+mod timezone;
 mod utils;
+mod watch;
+use std::sync::Arc;
+
 use shuttle_runtime::SecretStore;
 
 use events::Handler;
@@ -15,14 +34,54 @@ async fn serenity(
         .get("DISCORD_TOKEN")
         .expect("'DISCORD_TOKEN' was not found");
 
-    std::env::set_var("GROQ_API_KEY", secrets.get("GROQ_API_KEY").expect("'GROQ_API_KEY' was not found"));
-    std::env::set_var("CALBOT_CHAN", secrets.get("CALBOT_CHAN").expect("'CALBOT_CHAN' was not found"));
+    // Pick the LLM used for extraction (see `backend::from_env`), the default time zones
+    // (see `timezone::guild_timezone`), where access and events are saved (see `access::Access`
+    // and `store::Store`) and where .ics files are published (see `host::from_env`)
+    for key in [
+        "GROQ_API_KEY",
+        "CALBOT_BACKEND",
+        "CALBOT_LLM_ENDPOINT",
+        "CALBOT_LLM_MODEL",
+        "CALBOT_LLM_API_KEY",
+        "CALBOT_LLM_STRUCTURED",
+        "CALBOT_TIMEZONE",
+        "CALBOT_TIMEZONES",
+        "CALBOT_ACCESS_FILE",
+        "CALBOT_DB",
+        "CALBOT_PUBLIC_URL",
+        "CALBOT_HTTP_ADDR",
+        "CALBOT_ICS_HOST",
+        "CALBOT_CHAN",
+    ] {
+        if let Some(val) = secrets.get(key) {
+            std::env::set_var(key, val);
+        }
+    }
+
+    // Set gateway intents, which decides what events the bot will be notified about. GUILDS
+    // fills the cache with the channels and roles used to resolve mentions, and
+    // GUILD_SCHEDULED_EVENTS brings the scheduled events to import (see `Handler::import`), and
+    // GUILD_MESSAGE_REACTIONS the reactions that convert messages (see `reactions`).
+    let intents = GatewayIntents::GUILDS
+        | GatewayIntents::GUILD_MESSAGES
+        | GatewayIntents::MESSAGE_CONTENT
+        | GatewayIntents::GUILD_SCHEDULED_EVENTS
+        | GatewayIntents::GUILD_MESSAGE_REACTIONS;
 
-    // Set gateway intents, which decides what events the bot will be notified about
-    let intents = GatewayIntents::GUILD_MESSAGES | GatewayIntents::MESSAGE_CONTENT;
+    // Serve feeds and .ics files when they have somewhere to be linked from (see `http`)
+    let store = Arc::new(store::Store::from_env());
+    if http::public_url().is_some() {
+        tokio::spawn(http::serve(store.clone()));
+    }
 
     let client = Client::builder(&token, intents)
-        .event_handler(Handler)
+        .event_handler(Handler {
+            backend: backend::from_env(),
+            drafts: Default::default(),
+            access: access::Access::from_env(),
+            host: host::from_env(store.clone()),
+            store,
+        })
         .await
         .expect("Err creating client");
 
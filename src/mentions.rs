@@ -0,0 +1,122 @@
+//! User, role and channel mentions, which are `<@123>`-style tokens in the raw message content
+//! and would otherwise end up verbatim in titles and locations.
+
+use std::sync::LazyLock;
+
+use regex::Regex;
+use serenity::all::{ChannelId, ChannelType, Context, GuildId, RoleId, User, UserId};
+
+static MENTION: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<(@!?|@&|#)(\d+)>").unwrap());
+static EVERYONE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(^|[^\w.])@(?:everyone|here)\b[ \t]*").unwrap());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mention {
+    User(UserId),
+    Role(RoleId),
+    Channel(ChannelId),
+}
+
+/// Replaces every mention in `content` with what `resolve` returns for it, and removes
+/// `@everyone` and `@here`. Mentions `resolve` doesn't know are left as they are.
+pub fn replace_mentions(content: &str, resolve: impl Fn(Mention) -> Option<String>) -> String {
+    let content = EVERYONE.replace_all(content, "$1");
+    let content = MENTION.replace_all(&content, |caps: &regex::Captures| {
+        let Ok(id) = caps[2].parse::<u64>() else {
+            return caps[0].to_owned();
+        };
+        if id == 0 {
+            return caps[0].to_owned();
+        }
+        let mention = match &caps[1] {
+            "@&" => Mention::Role(RoleId::new(id)),
+            "#" => Mention::Channel(ChannelId::new(id)),
+            _ => Mention::User(UserId::new(id)),
+        };
+        resolve(mention).unwrap_or_else(|| caps[0].to_owned())
+    });
+    content.trim().to_owned()
+}
+
+/// How a channel reads in an announcement. Voice channels and stages are where the event
+/// happens, so they're written as a place.
+pub fn channel_name(name: &str, kind: ChannelType) -> String {
+    match kind {
+        ChannelType::Voice => format!("{name} voice channel"),
+        ChannelType::Stage => format!("{name} stage"),
+        _ => format!("#{name}"),
+    }
+}
+
+/// `content` with mentions replaced by display names, using the `users` Discord sends along with
+/// a message and the cache for everything else. Mentions of the bot itself are removed.
+pub fn resolve_mentions(
+    ctx: &Context,
+    guild_id: Option<GuildId>,
+    content: &str,
+    users: &[User],
+) -> String {
+    let bot_id = ctx.cache.current_user().id;
+    let guild = guild_id.and_then(|id| ctx.cache.guild(id));
+
+    replace_mentions(content, |mention| match mention {
+        Mention::User(id) if id == bot_id => Some(String::new()),
+        Mention::User(id) => {
+            if let Some(user) = users.iter().find(|u| u.id == id) {
+                let nick = user.member.as_ref().and_then(|m| m.nick.clone());
+                return Some(nick.unwrap_or_else(|| user.display_name().to_owned()));
+            }
+            let member = guild.as_ref()?.members.get(&id)?;
+            Some(member.display_name().to_owned())
+        }
+        Mention::Role(id) => Some(guild.as_ref()?.roles.get(&id)?.name.clone()),
+        Mention::Channel(id) => {
+            let channel = guild.as_ref()?.channels.get(&id)?;
+            Some(channel_name(&channel.name, channel.kind))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(mention: Mention) -> Option<String> {
+        match mention {
+            Mention::User(id) if id.get() == 1 => Some("Lomzem".to_owned()),
+            Mention::Role(id) if id.get() == 2 => Some("Officers".to_owned()),
+            Mention::Channel(id) if id.get() == 3 => {
+                Some(channel_name("Lounge", ChannelType::Voice))
+            }
+            Mention::Channel(id) if id.get() == 4 => {
+                Some(channel_name("announcements", ChannelType::Text))
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn replaces_mentions() {
+        assert_eq!(
+            replace_mentions(
+                "@everyone <@!1> and <@&2> host game night tomorrow 7-9pm in <#3>, see <#4>",
+                resolve
+            ),
+            "Lomzem and Officers host game night tomorrow 7-9pm in Lounge voice channel, \
+             see #announcements"
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_mentions() {
+        assert_eq!(
+            replace_mentions("<@9> <@&9> <#9> @here", resolve),
+            "<@9> <@&9> <#9>"
+        );
+        // not a mention of anyone
+        assert_eq!(
+            replace_mentions("mail me@everyone.org", resolve),
+            "mail me@everyone.org"
+        );
+    }
+}
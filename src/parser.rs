@@ -1,56 +1,109 @@
-use chrono::{Datelike, Days, NaiveDate, NaiveTime, Weekday};
-use icalendar::{Calendar, Component, Event, EventLike};
-use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use chrono::{Datelike, Days, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta};
+use chrono_tz::Tz;
+use icalendar::{Calendar, CalendarDateTime, Component, DatePerhapsTime, Event, EventLike};
 use serde::Deserialize;
+use uuid::Uuid;
 
-const GROQ_ENDPOINT: &str = "https://api.groq.com/openai/v1/chat/completions";
-const PROMPT_INSTRUCTIONS: &str = include_str!("llm-prompt.txt");
-const MAX_COMPLETION_TOKEN: usize = 300;
-
-#[derive(Deserialize, Debug)]
-struct GroqResponse {
-    choices: Vec<GroqChoice>,
-}
+use crate::{
+    backend::ExtractionBackend,
+    date_grammar,
+    recurrence::{parse_weekday, Frequency, RecurrenceRule},
+    rules::{self, Confidence},
+    timestamps::{self, Timestamp},
+    timezone,
+};
 
+const PROMPT_INSTRUCTIONS: &str = include_str!("llm-prompt.txt");
+/// JSON schema of [`Extraction`], for backends that can enforce structured output.
+const OUTPUT_SCHEMA: &str = include_str!("llm-schema.json");
+/// Discord allows 25 buttons per message, one of which is reserved for "Add all".
+pub const MAX_EVENTS: usize = 24;
+/// Anything longer is much more likely to be a misread date than a real event.
+pub const MAX_EVENT_DAYS: i64 = 14;
+
+/// What the LLM (or the offline rules in [`rules`]) extracted from a message, see
+/// `llm-schema.json`.
 #[derive(Deserialize, Debug)]
-struct GroqChoice {
-    message: GroqMessage,
+#[serde(deny_unknown_fields)]
+pub struct Extraction {
+    pub events: Vec<ExtractedEvent>,
 }
 
+/// Dates and times are still encoded, see [`date_grammar`].
 #[derive(Deserialize, Debug)]
-struct GroqMessage {
-    content: String,
+#[serde(deny_unknown_fields)]
+pub struct ExtractedEvent {
+    pub title: String,
+    pub date: String,
+    pub enddate: Option<String>,
+    pub starttime: Option<String>,
+    pub endtime: Option<String>,
+    pub timezone: Option<String>,
+    pub location: Option<String>,
+    pub description: Option<String>,
+    pub recurrence: Option<ExtractedRecurrence>,
 }
 
 #[derive(Deserialize, Debug)]
-struct GroqOutput {
-    title: String,
-    date: String,
-    starttime: String,
-    endtime: String,
-    location: String,
-    description: Option<String>,
+#[serde(deny_unknown_fields)]
+pub struct ExtractedRecurrence {
+    pub frequency: Frequency,
+    pub interval: Option<u32>,
+    #[serde(default)]
+    pub weekdays: Vec<String>,
+    pub until: Option<String>,
+    pub count: Option<u32>,
+    #[serde(default)]
+    pub exceptions: Vec<String>,
 }
 
 #[derive(Debug)]
 pub enum Error {
     ParseFailure,
     NoResponse,
+    /// The LLM's output is missing a field, or has one that doesn't make sense. `field` is the
+    /// path to it, e.g. `events[0].starttime`.
+    InvalidOutput {
+        field: String,
+        reason: String,
+    },
+    InvalidDuration {
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    },
     Reqwest(reqwest::Error),
+    /// The .ics files couldn't be published, see `host`
+    Publish(String),
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Error::ParseFailure => write!(f, "Failed to parse response from Groq API"),
-            Error::NoResponse => write!(f, "No response from Groq API"),
+            Error::ParseFailure => write!(f, "Failed to parse response from LLM"),
+            Error::NoResponse => write!(f, "No response from LLM"),
+            Error::InvalidOutput { field, reason } => {
+                write!(f, "Invalid `{field}` in LLM output: {reason}")
+            }
+            Error::InvalidDuration { start, end } if end < start => {
+                write!(f, "Event ends ({end}) before it starts ({start})")
+            }
+            Error::InvalidDuration { start, end } => write!(
+                f,
+                "Event runs from {start} to {end}, longer than {MAX_EVENT_DAYS} days"
+            ),
             Error::Reqwest(e) => write!(f, "Reqwest error: {}", e),
+            Error::Publish(e) => write!(f, "Failed to publish calendar: {e}"),
         }
     }
 }
 
 impl std::error::Error for Error {}
 
+/// The prompt with the date grammar filled in, see [`date_grammar`].
+fn prompt_instructions() -> String {
+    PROMPT_INSTRUCTIONS.replace("{date_grammar}", &date_grammar::prompt_section())
+}
+
 fn parse_date(date_str: &str, msg_date: &NaiveDate) -> Result<NaiveDate, Error> {
     let mut date_iter = date_str.chars();
     match date_iter.next() {
@@ -64,17 +117,9 @@ fn parse_date(date_str: &str, msg_date: &NaiveDate) -> Result<NaiveDate, Error>
                 .ok_or(Error::ParseFailure)
         }
         Some('_') => {
-            let weekday = match date_iter.collect::<String>().to_lowercase().as_str() {
-                "mon" => Weekday::Mon,
-                "tue" => Weekday::Tue,
-                "wed" => Weekday::Wed,
-                "thu" => Weekday::Thu,
-                "fri" => Weekday::Fri,
-                "sat" => Weekday::Sat,
-                "sun" => Weekday::Sun,
-                _ => return Err(Error::ParseFailure),
-            }
-            .num_days_from_monday();
+            let weekday = parse_weekday(&date_iter.collect::<String>())
+                .ok_or(Error::ParseFailure)?
+                .num_days_from_monday();
             let orig_weekday = msg_date.weekday().num_days_from_monday();
             dbg!(&orig_weekday);
             dbg!(&weekday);
@@ -88,70 +133,303 @@ fn parse_date(date_str: &str, msg_date: &NaiveDate) -> Result<NaiveDate, Error>
                 .checked_add_days(Days::new(days_delta))
                 .ok_or(Error::ParseFailure)
         }
-        Some('x') => NaiveDate::parse_from_str(
-            &format!("{}{}", msg_date.year(), date_iter.collect::<String>()),
-            "%Y%m%d",
-        )
-        .map_err(|_| Error::ParseFailure),
+        Some('x') => {
+            let digits = date_iter.collect::<String>();
+            match digits.len() {
+                // an explicit year is taken as is
+                8 => NaiveDate::parse_from_str(&digits, "%Y%m%d").map_err(|_| Error::ParseFailure),
+                // otherwise use the next time that day comes around, so "1/15" said in December
+                // means next January. Feb 29th can be up to 8 years away.
+                4 => (msg_date.year()..=msg_date.year() + 8)
+                    .filter_map(|year| {
+                        NaiveDate::parse_from_str(&format!("{year}{digits}"), "%Y%m%d").ok()
+                    })
+                    .find(|date| date >= msg_date)
+                    .ok_or(Error::ParseFailure),
+                _ => Err(Error::ParseFailure),
+            }
+        }
         _ => Err(Error::ParseFailure),
     }
 }
 
-pub async fn parse_msg(msg: &str, message_date: &NaiveDate) -> Result<Calendar, Error> {
-    let groq_key = std::env::var("GROQ_API_KEY").expect("GROQ_API_KEY missing");
+/// Turns a message into a calendar. Simple messages are handled by the offline [`rules`], the
+/// LLM is only asked when they aren't sure. Without a backend the rules' best guess is used.
+///
+/// Discord timestamp tags are written out in `default_tz` (or UTC) before either sees the
+/// message, and the times they encode win over whatever was extracted, see [`timestamps`].
+pub async fn parse_msg(
+    backend: Option<&dyn ExtractionBackend>,
+    msg: &str,
+    message_date: &NaiveDate,
+    default_tz: Option<Tz>,
+) -> Result<Calendar, Error> {
+    parse_msg_with(
+        backend,
+        msg,
+        message_date,
+        default_tz,
+        &Overrides::default(),
+    )
+    .await
+}
 
-    let full_prompt = [PROMPT_INSTRUCTIONS, msg].join("\r\n");
+/// [`parse_msg`] for messages nobody asked the bot to read, which are only converted if they look
+/// like they announce an event. Messages the rules find no date in are skipped without asking the
+/// LLM, and the LLM decides about the rest. Without one, only messages the rules are sure about
+/// count.
+pub async fn detect_event(
+    backend: Option<&dyn ExtractionBackend>,
+    msg: &str,
+    message_date: &NaiveDate,
+    default_tz: Option<Tz>,
+) -> Result<Calendar, Error> {
+    let resolved = timestamps::resolve(msg, default_tz.unwrap_or(chrono_tz::UTC));
+    let looks_like_event = !resolved.stamps.is_empty()
+        || rules::extract(&resolved.text)
+            .is_some_and(|found| backend.is_some() || found.confidence == Confidence::High);
+    if !looks_like_event {
+        return Err(Error::ParseFailure);
+    }
+    parse_msg(backend, msg, message_date, default_tz).await
+}
 
-    let req_body = serde_json::json!({
-        "model": "llama-3.3-70b-versatile",
-        // "model": "llama-3.2-90b-vision-preview",
-        "max_completion_tokens": MAX_COMPLETION_TOKEN,
-        "messages": [
-        {
-            "role": "user",
-            "content": full_prompt,
+/// Fields given explicitly, e.g. as options of the `/event` command. They replace whatever was
+/// extracted for every event, and are already encoded like the LLM's output.
+#[derive(Debug, Default)]
+pub struct Overrides {
+    /// In the date grammar, see [`date_grammar`]
+    pub date: Option<String>,
+    /// As HHMM
+    pub starttime: Option<String>,
+    /// As HHMM
+    pub endtime: Option<String>,
+    pub location: Option<String>,
+}
+
+impl Overrides {
+    /// When nothing could be extracted, an explicit date is enough for an event titled `msg`.
+    fn fallback_event(&self, msg: &str) -> Option<Extraction> {
+        let date = self.date.clone()?;
+        Some(Extraction {
+            events: vec![ExtractedEvent {
+                title: msg.trim().to_owned(),
+                date,
+                enddate: None,
+                starttime: None,
+                endtime: None,
+                timezone: None,
+                location: None,
+                description: None,
+                recurrence: None,
+            }],
+        })
+    }
+
+    fn apply(&self, extraction: &mut Extraction) {
+        for event in &mut extraction.events {
+            if let Some(date) = &self.date {
+                event.date = date.clone();
+                event.enddate = None;
+            }
+            if let Some(starttime) = &self.starttime {
+                event.starttime = Some(starttime.clone());
+            }
+            if let Some(endtime) = &self.endtime {
+                event.endtime = Some(endtime.clone());
+            }
+            if let Some(location) = &self.location {
+                event.location = Some(location.clone());
+            }
         }
-    ]});
+    }
+}
 
-    let request = reqwest::Client::new()
-        .post(GROQ_ENDPOINT)
-        .json(&req_body)
-        .header(CONTENT_TYPE, "application/json")
-        .header(AUTHORIZATION, format!("Bearer {}", groq_key));
+/// [`parse_msg`] with some of the fields given explicitly.
+pub async fn parse_msg_with(
+    backend: Option<&dyn ExtractionBackend>,
+    msg: &str,
+    message_date: &NaiveDate,
+    default_tz: Option<Tz>,
+    overrides: &Overrides,
+) -> Result<Calendar, Error> {
+    let stamp_tz = default_tz.unwrap_or(chrono_tz::UTC);
+    let resolved = timestamps::resolve(msg, stamp_tz);
+    let msg = resolved.text.as_str();
+
+    let mut extraction = match extract(backend, msg).await {
+        Err(Error::ParseFailure) => overrides.fallback_event(msg).ok_or(Error::ParseFailure)?,
+        extraction => extraction?,
+    };
 
-    let groq_resp: GroqResponse = request
-        .send()
-        .await
-        .map_err(Error::Reqwest)?
-        .json()
-        .await
-        .map_err(Error::Reqwest)?;
+    extraction.pin_timestamps(&resolved.stamps, stamp_tz, message_date);
+    overrides.apply(&mut extraction);
+    extraction.to_ical(message_date, default_tz)
+}
 
-    let output = if let Some(groq_choice) = groq_resp.choices.first() {
-        &groq_choice.message.content
-    } else {
-        return Err(Error::NoResponse);
+/// Runs the rules, and the LLM if they aren't sure.
+async fn extract(backend: Option<&dyn ExtractionBackend>, msg: &str) -> Result<Extraction, Error> {
+    let pre_pass = rules::extract(msg);
+    let extraction = match (pre_pass, backend) {
+        (Some(found), _) if found.confidence == Confidence::High => found.extraction,
+        (Some(found), None) => found.extraction,
+        (None, None) => return Err(Error::ParseFailure),
+        (pre_pass, Some(backend)) => match llm_extract(backend, msg).await {
+            Ok(extraction) => extraction,
+            // a guess beats no answer when the LLM is unreachable
+            Err(e @ (Error::NoResponse | Error::Reqwest(_))) => match pre_pass {
+                Some(found) => {
+                    println!("LLM failed ({e}), using the rule-based extraction");
+                    found.extraction
+                }
+                None => return Err(e),
+            },
+            Err(e) => return Err(e),
+        },
     };
+    Ok(extraction)
+}
+
+async fn llm_extract(backend: &dyn ExtractionBackend, msg: &str) -> Result<Extraction, Error> {
+    let full_prompt = [&prompt_instructions(), msg].join("\r\n");
+
+    let schema = serde_json::from_str(OUTPUT_SCHEMA).expect("llm-schema.json should be valid");
+    let output = backend.complete(&full_prompt, &schema).await?;
 
     dbg!(&output);
 
-    if output.is_empty() || output.to_lowercase().contains("failed") {
+    let extraction = parse_output(&output)?;
+    if extraction.events.is_empty() {
+        // the model found nothing that looks like an event
+        return Err(Error::ParseFailure);
+    }
+    Ok(extraction)
+}
+
+fn parse_output(output: &str) -> Result<Extraction, Error> {
+    // backends without structured output like to wrap the JSON in a code block
+    let output = output.trim();
+    let output = output
+        .strip_prefix("```json")
+        .or_else(|| output.strip_prefix("```"))
+        .and_then(|o| o.strip_suffix("```"))
+        .unwrap_or(output);
+    if output.trim().is_empty() {
         return Err(Error::ParseFailure);
     }
 
-    let groq_output: GroqOutput = toml::from_str(output).map_err(|_| Error::ParseFailure)?;
-    groq_output
-        .to_ical(message_date)
-        .map_err(|_| Error::ParseFailure)
+    let de = &mut serde_json::Deserializer::from_str(output);
+    serde_path_to_error::deserialize(de).map_err(|e| Error::InvalidOutput {
+        field: e.path().to_string(),
+        reason: e.inner().to_string(),
+    })
+}
+
+fn invalid(field: &str, reason: impl Into<String>) -> Error {
+    Error::InvalidOutput {
+        field: field.to_owned(),
+        reason: reason.into(),
+    }
+}
+
+impl Extraction {
+    /// Replaces the extracted start (and end) of events with the exact times of the message's
+    /// timestamp tags, which `tz` is the zone they were written out in.
+    ///
+    /// Tags are matched to events by date. An announcement of a single event takes the first
+    /// tag (and the second as its end) even if the extracted date disagrees.
+    fn pin_timestamps(&mut self, stamps: &[Timestamp], tz: Tz, message_date: &NaiveDate) {
+        let timed: Vec<_> = stamps
+            .iter()
+            .filter(|s| s.has_time())
+            .map(|s| s.at.with_timezone(&tz).naive_local())
+            .collect();
+        if timed.is_empty() {
+            return;
+        }
+        let single = self.events.len() == 1;
+
+        for event in &mut self.events {
+            let date = parse_date(&event.date, message_date).ok();
+            let mut matching: Vec<_> = timed.iter().filter(|t| Some(t.date()) == date).collect();
+            if matching.is_empty() && single {
+                matching = timed.iter().collect();
+            }
+            let Some(start) = matching.first() else {
+                continue;
+            };
+
+            event.date = start.format("x%Y%m%d").to_string();
+            event.starttime = Some(start.format("%H%M").to_string());
+            if let Some(end) = matching.get(1) {
+                event.endtime = Some(end.format("%H%M").to_string());
+                event.enddate =
+                    (end.date() != start.date()).then(|| end.format("x%Y%m%d").to_string());
+            }
+            event.timezone = Some(tz.name().to_owned());
+        }
+    }
+
+    /// Builds a calendar with every event that could be converted. Events the model got wrong
+    /// are dropped so they don't take the rest of the announcement down with them, unless none
+    /// are left, in which case the first event's error is returned.
+    fn to_ical(&self, message_date: &NaiveDate, default_tz: Option<Tz>) -> Result<Calendar, Error> {
+        let mut calendar = Calendar::new();
+        let mut first_err = None;
+        for (i, extracted) in self.events.iter().take(MAX_EVENTS).enumerate() {
+            match extracted.to_event(message_date, default_tz) {
+                Ok(mut event) => {
+                    // kept from now on, so the event can be found again (see `store`)
+                    event.uid(&Uuid::new_v4().to_string());
+                    calendar.push(event);
+                }
+                Err(e) => {
+                    let e = match e {
+                        Error::InvalidOutput { field, reason } => Error::InvalidOutput {
+                            field: format!("events[{i}].{field}"),
+                            reason,
+                        },
+                        e => e,
+                    };
+                    println!("Skipping event {:?}: {e}", extracted.title);
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+
+        if calendar.components.is_empty() {
+            // surface why the event was rejected, e.g. an impossible duration
+            return Err(first_err.unwrap_or(Error::ParseFailure));
+        }
+        Ok(calendar.done())
+    }
 }
 
-impl GroqOutput {
-    fn to_ical(&self, message_date: &NaiveDate) -> Result<Calendar, Error> {
-        let date = parse_date(&self.date, message_date)?;
-        let starttime =
-            NaiveTime::parse_from_str(&self.starttime, "%H%M").map_err(|_| Error::ParseFailure)?;
-        let endtime =
-            NaiveTime::parse_from_str(&self.endtime, "%H%M").map_err(|_| Error::ParseFailure)?;
+impl ExtractedEvent {
+    /// Errors about a field name it relative to the event, e.g. `starttime`.
+    fn to_event(&self, message_date: &NaiveDate, default_tz: Option<Tz>) -> Result<Event, Error> {
+        let date = parse_field_date("date", &self.date, message_date)?;
+        let starttime = non_empty(&self.starttime)
+            .map(|t| parse_field_time("starttime", t))
+            .transpose()?;
+        let endtime = non_empty(&self.endtime)
+            .map(|t| parse_field_time("endtime", t))
+            .transpose()?;
+        let enddate = match non_empty(&self.enddate) {
+            Some(enddate) => parse_field_date("enddate", enddate, message_date)?,
+            // "10pm-2am" ends the day after it starts
+            None => match (starttime, endtime) {
+                (Some(starttime), Some(endtime)) if endtime < starttime => {
+                    date.succ_opt().ok_or(Error::ParseFailure)?
+                }
+                _ => date,
+            },
+        };
+        check_duration(
+            date.and_time(starttime.unwrap_or_default()),
+            enddate.and_time(endtime.or(starttime).unwrap_or_default()),
+        )?;
+
         let description = match &self.description {
             Some(desc) => desc,
             None => "",
@@ -172,23 +450,229 @@ impl GroqOutput {
             .collect::<Vec<String>>()
             .join(" ");
 
-        let event = Event::new()
-            .summary(&title)
-            .starts(date.and_time(starttime))
-            .ends(date.and_time(endtime))
-            .description(description)
-            .location(&self.location)
-            .done();
-        Ok(Calendar::new().push(event).done())
+        let mut event = Event::new();
+        event.summary(&title).description(description);
+        if let Some(location) = non_empty(&self.location) {
+            event.location(location);
+        }
+
+        // a zone stated in the message wins over the guild's default
+        let tz = match non_empty(&self.timezone) {
+            Some(zone) => timezone::parse_zone(zone).or(default_tz),
+            None => default_tz,
+        };
+
+        let (dtstart, dtend): (DatePerhapsTime, DatePerhapsTime) = match (starttime, endtime) {
+            // no times at all means the event lasts the whole day(s), DTEND is exclusive
+            (None, None) => (
+                date.into(),
+                enddate.succ_opt().ok_or(Error::ParseFailure)?.into(),
+            ),
+            // a lone start time is used for both ends
+            (Some(starttime), endtime) => (
+                zoned(date.and_time(starttime), tz),
+                zoned(enddate.and_time(endtime.unwrap_or(starttime)), tz),
+            ),
+            (None, Some(_)) => return Err(invalid("starttime", "missing, but endtime is set")),
+        };
+        event.starts(dtstart.clone()).ends(dtend);
+
+        if let Some(recurrence) = &self.recurrence {
+            let rule = recurrence.to_rule(message_date)?;
+            event.add_property("RRULE", rule.to_rrule(&dtstart));
+            for (i, exception) in recurrence.exceptions.iter().enumerate() {
+                // EXDATE has to match DTSTART's value type and time of day to cancel an occurrence
+                let field = format!("recurrence.exceptions[{i}]");
+                let skipped = parse_field_date(&field, exception, message_date)?;
+                event.append_multi_property(on_date(&dtstart, skipped).to_property("EXDATE"));
+            }
+        }
+        Ok(event.done())
+    }
+}
+
+/// An event as someone corrected it in the edit form, encoded like the LLM's output.
+#[derive(Debug)]
+pub struct EventEdit {
+    pub title: String,
+    /// In the date grammar, see [`date_grammar`]
+    pub date: String,
+    /// As HHMM, `None` for an all-day event
+    pub starttime: Option<String>,
+    pub endtime: Option<String>,
+    pub location: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Rebuilds `old` with the fields of `edit`, keeping its time zone, how many days it spans and
+/// how it repeats. Relative dates are relative to `today`.
+pub fn edit_event(old: &Event, edit: &EventEdit, today: &NaiveDate) -> Result<Event, Error> {
+    let old_start = old.get_start().ok_or(Error::ParseFailure)?;
+    let old_end = old.get_end().unwrap_or_else(|| old_start.clone());
+    let (tz, start_date) = match &old_start {
+        DatePerhapsTime::Date(date) => (None, *date),
+        DatePerhapsTime::DateTime(CalendarDateTime::Floating(dt)) => (None, dt.date()),
+        DatePerhapsTime::DateTime(CalendarDateTime::Utc(dt)) => {
+            (Some(chrono_tz::UTC), dt.date_naive())
+        }
+        DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone { date_time, tzid }) => {
+            (tzid.parse().ok(), date_time.date())
+        }
+    };
+    let end_date = match &old_end {
+        // DTEND of an all-day event is exclusive
+        DatePerhapsTime::Date(date) => date.pred_opt().unwrap_or(*date),
+        DatePerhapsTime::DateTime(CalendarDateTime::Floating(dt)) => dt.date(),
+        DatePerhapsTime::DateTime(CalendarDateTime::Utc(dt)) => dt.date_naive(),
+        DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone { date_time, .. }) => {
+            date_time.date()
+        }
+    };
+    let days = (end_date - start_date).num_days().max(0);
+    let date = parse_field_date("date", &edit.date, today)?;
+    let enddate = (days > 0)
+        .then(|| date.checked_add_days(Days::new(days as u64)))
+        .flatten()
+        .map(|d| d.format("x%Y%m%d").to_string());
+
+    let extracted = ExtractedEvent {
+        title: edit.title.clone(),
+        date: date.format("x%Y%m%d").to_string(),
+        enddate,
+        starttime: edit.starttime.clone(),
+        endtime: edit.endtime.clone(),
+        timezone: None,
+        location: edit.location.clone(),
+        description: edit.description.clone(),
+        recurrence: None,
+    };
+    let mut event = extracted.to_event(today, tz)?;
+    // people mean the title exactly as they typed it
+    event.summary(edit.title.trim());
+    if let Some(uid) = old.get_uid() {
+        event.uid(uid);
+    }
+    if let Some(sequence) = old.get_sequence() {
+        event.sequence(sequence);
+    }
+
+    let new_start = event.get_start().ok_or(Error::ParseFailure)?;
+    if let Some(rule) = old
+        .property_value("RRULE")
+        .and_then(|rrule| RecurrenceRule::from_rrule(rrule, &old_start))
+    {
+        event.add_property("RRULE", rule.to_rrule(&new_start));
+    }
+    if let Some(exdates) = old.multi_properties().get("EXDATE") {
+        for exdate in exdates {
+            let Some(skipped) = exdate
+                .value()
+                .get(..8)
+                .and_then(|d| NaiveDate::parse_from_str(d, "%Y%m%d").ok())
+            else {
+                continue;
+            };
+            event.append_multi_property(on_date(&new_start, skipped).to_property("EXDATE"));
+        }
+    }
+    Ok(event.done())
+}
+
+fn zoned(date_time: NaiveDateTime, tz: Option<Tz>) -> DatePerhapsTime {
+    match tz {
+        Some(chrono_tz::UTC) => CalendarDateTime::Utc(date_time.and_utc()).into(),
+        Some(tz) => CalendarDateTime::WithTimezone {
+            date_time,
+            tzid: tz.name().to_owned(),
+        }
+        .into(),
+        None => date_time.into(),
+    }
+}
+
+/// `dt` moved to another day, keeping its time of day and zone.
+fn on_date(dt: &DatePerhapsTime, date: NaiveDate) -> DatePerhapsTime {
+    match dt {
+        DatePerhapsTime::Date(_) => date.into(),
+        DatePerhapsTime::DateTime(CalendarDateTime::Floating(dt)) => {
+            date.and_time(dt.time()).into()
+        }
+        DatePerhapsTime::DateTime(CalendarDateTime::Utc(dt)) => {
+            CalendarDateTime::Utc(date.and_time(dt.time()).and_utc()).into()
+        }
+        DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone { date_time, tzid }) => {
+            CalendarDateTime::WithTimezone {
+                date_time: date.and_time(date_time.time()),
+                tzid: tzid.clone(),
+            }
+            .into()
+        }
+    }
+}
+
+/// Rejects events that end before they start or run for longer than [`MAX_EVENT_DAYS`].
+fn check_duration(start: NaiveDateTime, end: NaiveDateTime) -> Result<(), Error> {
+    if end < start || end - start > TimeDelta::days(MAX_EVENT_DAYS) {
+        return Err(Error::InvalidDuration { start, end });
+    }
+    Ok(())
+}
+
+/// The model sometimes writes `key = ""` instead of leaving a key out.
+fn non_empty(val: &Option<String>) -> Option<&str> {
+    val.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+fn parse_field_time(field: &str, time_str: &str) -> Result<NaiveTime, Error> {
+    NaiveTime::parse_from_str(time_str, "%H%M")
+        .map_err(|_| invalid(field, format!("`{time_str}` is not a time in HHMM format")))
+}
+
+fn parse_field_date(field: &str, date_str: &str, msg_date: &NaiveDate) -> Result<NaiveDate, Error> {
+    parse_date(date_str, msg_date).map_err(|_| {
+        invalid(
+            field,
+            format!("`{date_str}` doesn't follow the date grammar"),
+        )
+    })
+}
+
+impl ExtractedRecurrence {
+    fn to_rule(&self, message_date: &NaiveDate) -> Result<RecurrenceRule, Error> {
+        let weekdays = self
+            .weekdays
+            .iter()
+            .enumerate()
+            .map(|(i, d)| {
+                parse_weekday(d).ok_or_else(|| {
+                    invalid(
+                        &format!("recurrence.weekdays[{i}]"),
+                        format!("`{d}` is not a weekday"),
+                    )
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        let until = non_empty(&self.until)
+            .map(|d| parse_field_date("recurrence.until", d, message_date))
+            .transpose()?;
+
+        Ok(RecurrenceRule {
+            frequency: self.frequency,
+            interval: self.interval.unwrap_or(1).max(1),
+            weekdays,
+            until,
+            count: self.count,
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use chrono::{Days, Local, Timelike};
+    use chrono::{Days, Local, TimeZone, Timelike, Utc};
     use icalendar::{Component, EventLike};
 
     use super::*;
+    use crate::backend;
 
     #[tokio::test]
     async fn parse_date_relwd_early_late() {
@@ -235,13 +719,675 @@ mod tests {
         assert_eq!(res, intended_date);
     }
 
-    // by default, ignore tests that require a POST request to the Groq API
+    #[tokio::test]
+    async fn parse_date_exact_same_year() {
+        let date_msg = "x1031";
+        let date = NaiveDate::from_ymd_opt(2009, 6, 9).unwrap();
+        let res = parse_date(date_msg, &date).unwrap();
+        let intended_date = NaiveDate::from_ymd_opt(2009, 10, 31).unwrap();
+        assert_eq!(res, intended_date);
+    }
+
+    #[tokio::test]
+    async fn parse_date_exact_same_day() {
+        let date_msg = "x0609";
+        let date = NaiveDate::from_ymd_opt(2009, 6, 9).unwrap();
+        let res = parse_date(date_msg, &date).unwrap();
+        assert_eq!(res, date);
+    }
+
+    #[tokio::test]
+    async fn parse_date_exact_year_rollover() {
+        let date_msg = "x0115";
+        let date = NaiveDate::from_ymd_opt(2024, 12, 20).unwrap();
+        let res = parse_date(date_msg, &date).unwrap();
+        let intended_date = NaiveDate::from_ymd_opt(2025, 1, 15).unwrap();
+        assert_eq!(res, intended_date);
+    }
+
+    #[tokio::test]
+    async fn parse_date_exact_leap_day() {
+        let date_msg = "x0229";
+        let date = NaiveDate::from_ymd_opt(2025, 1, 10).unwrap();
+        let res = parse_date(date_msg, &date).unwrap();
+        let intended_date = NaiveDate::from_ymd_opt(2028, 2, 29).unwrap();
+        assert_eq!(res, intended_date);
+    }
+
+    #[tokio::test]
+    async fn parse_date_exact_explicit_year() {
+        let date_msg = "x20240115";
+        let date = NaiveDate::from_ymd_opt(2024, 12, 20).unwrap();
+        let res = parse_date(date_msg, &date).unwrap();
+        let intended_date = NaiveDate::from_ymd_opt(2024, 1, 15).unwrap();
+        assert_eq!(res, intended_date);
+    }
+
+    #[tokio::test]
+    async fn parse_date_exact_invalid() {
+        let date = NaiveDate::from_ymd_opt(2024, 12, 20).unwrap();
+        assert!(parse_date("x1340", &date).is_err());
+        assert!(parse_date("x115", &date).is_err());
+        assert!(parse_date("x20250230", &date).is_err());
+    }
+
+    #[tokio::test]
+    async fn parse_date_relative_decimal() {
+        let date = NaiveDate::from_ymd_opt(2025, 2, 3).unwrap();
+        let res = parse_date("+12", &date).unwrap();
+        // "+12" used to be read as hex by the model, it's 12 days, not 18
+        assert_eq!(res, NaiveDate::from_ymd_opt(2025, 2, 15).unwrap());
+        let res = parse_date("+31", &date).unwrap();
+        assert_eq!(res, NaiveDate::from_ymd_opt(2025, 3, 6).unwrap());
+        assert!(parse_date("+1A", &date).is_err());
+        assert!(parse_date("+0x12", &date).is_err());
+    }
+
+    #[test]
+    fn date_grammar_examples() {
+        for form in date_grammar::DATE_FORMS {
+            for example in form.examples {
+                assert!(example.encoded.starts_with(form.prefix));
+                let msg_date = example.message_date.parse().unwrap();
+                let resolved = parse_date(example.encoded, &msg_date)
+                    .unwrap_or_else(|_| panic!("{} should parse", example.encoded));
+                assert_eq!(
+                    resolved.to_string(),
+                    example.resolved,
+                    "{} sent on {}",
+                    example.encoded,
+                    example.message_date
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn date_grammar_in_prompt() {
+        let prompt = prompt_instructions();
+        assert!(!prompt.contains("{date_grammar}"));
+        assert!(prompt.contains(&format!(
+            "# Date Formats (version {})",
+            date_grammar::DATE_GRAMMAR_VERSION
+        )));
+        assert!(!prompt.to_lowercase().contains("hex"));
+
+        // anything the prompt doesn't describe must not parse either
+        let date = NaiveDate::from_ymd_opt(2025, 2, 3).unwrap();
+        for prefix in ['-', '#', 'd', '0'] {
+            assert!(!date_grammar::DATE_FORMS.iter().any(|f| f.prefix == prefix));
+            assert!(parse_date(&format!("{prefix}1"), &date).is_err());
+        }
+    }
+
+    /// Serves a single canned OpenAI-style chat completion on localhost and returns its URL.
+    async fn stand_in_llm(reply: &'static str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 16384];
+            let _ = stream.read(&mut buf).await.unwrap();
+            let body = serde_json::json!({
+                "choices": [{ "message": { "role": "assistant", "content": reply } }]
+            })
+            .to_string();
+            let resp = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(resp.as_bytes()).await.unwrap();
+        });
+        format!("http://{addr}/v1/chat/completions")
+    }
+
+    #[tokio::test]
+    async fn stand_in_openai_backend() {
+        let endpoint = stand_in_llm(
+            r#"{"events": [{"title": "acm meeting", "date": "+1", "starttime": "1600", "endtime": "1800", "location": "OCNL 241"}]}"#,
+        )
+        .await;
+        let backend = backend::OpenAiCompatible::new(
+            &endpoint,
+            "stand-in",
+            None,
+            backend::StructuredOutput::JsonSchema,
+        );
+        let date = NaiveDate::from_ymd_opt(2024, 2, 28).unwrap();
+        let calendar = parse_msg(
+            Some(&backend),
+            "ACM meets after the career fair, 4-6pm in OCNL 241",
+            &date,
+            None,
+        )
+        .await
+        .unwrap();
+
+        let event = calendar.components.first().unwrap().as_event().unwrap();
+        assert_eq!(event.get_summary(), Some("Acm Meeting"));
+        assert_eq!(event.get_location(), Some("OCNL 241"));
+        assert_eq!(
+            event.get_start(),
+            Some(icalendar::DatePerhapsTime::DateTime(
+                icalendar::CalendarDateTime::Floating(
+                    NaiveDate::from_ymd_opt(2024, 2, 29)
+                        .unwrap()
+                        .and_hms_opt(16, 0, 0)
+                        .unwrap()
+                )
+            ))
+        );
+    }
+
+    /// A backend that must not be called.
+    struct Unreachable;
+
+    #[serenity::async_trait]
+    impl ExtractionBackend for Unreachable {
+        async fn complete(&self, _: &str, _: &serde_json::Value) -> Result<String, Error> {
+            panic!("simple messages shouldn't need the LLM");
+        }
+    }
+
+    #[tokio::test]
+    async fn rules_pre_pass() {
+        let date = NaiveDate::from_ymd_opt(2024, 2, 28).unwrap();
+        let calendar = parse_msg(
+            Some(&Unreachable),
+            "ACM meeting tomorrow 4-6pm in OCNL 241",
+            &date,
+            None,
+        )
+        .await
+        .unwrap();
+        let event = calendar.components.first().unwrap().as_event().unwrap();
+        assert_eq!(event.get_summary(), Some("ACM Meeting"));
+        assert_eq!(event.get_location(), Some("OCNL 241"));
+
+        // without a backend, the rules' guess is used even when they aren't sure
+        let calendar = parse_msg(None, "Career fair tomorrow in Acker Gym", &date, None)
+            .await
+            .unwrap();
+        let event = calendar.components.first().unwrap().as_event().unwrap();
+        assert_eq!(event.get_start(), Some(date.succ_opt().unwrap().into()));
+
+        assert!(matches!(
+            parse_msg(None, "69420", &date, None).await,
+            Err(Error::ParseFailure)
+        ));
+    }
+
+    #[tokio::test]
+    async fn detection() {
+        let date = NaiveDate::from_ymd_opt(2024, 2, 28).unwrap();
+        let msg = "ACM meeting tomorrow 4-6pm in OCNL 241";
+        assert!(detect_event(Some(&Unreachable), msg, &date, None)
+            .await
+            .is_ok());
+        // chatter without a date never gets to the LLM
+        assert!(matches!(
+            detect_event(
+                Some(&Unreachable),
+                "Thanks everyone for coming!",
+                &date,
+                None
+            )
+            .await,
+            Err(Error::ParseFailure)
+        ));
+        // and without one, a guess isn't enough
+        assert!(matches!(
+            detect_event(None, "Career fair tomorrow in Acker Gym", &date, None).await,
+            Err(Error::ParseFailure)
+        ));
+    }
+
+    #[tokio::test]
+    async fn discord_timestamps() {
+        let la = chrono_tz::America::Los_Angeles;
+        let date = NaiveDate::from_ymd_opt(2025, 2, 1).unwrap();
+        // 2025-02-06 00:00 and 02:00 UTC, the 5th at 4pm and 6pm in LA
+        let calendar = parse_msg(
+            None,
+            "Game night <t:1738800000:F> to <t:1738807200:t> in BMU 210",
+            &date,
+            Some(la),
+        )
+        .await
+        .unwrap();
+        let event = calendar.components.first().unwrap().as_event().unwrap();
+        let at = |h| CalendarDateTime::WithTimezone {
+            date_time: NaiveDate::from_ymd_opt(2025, 2, 5)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap(),
+            tzid: "America/Los_Angeles".to_owned(),
+        };
+        assert_eq!(event.get_start(), Some(at(16).into()));
+        assert_eq!(event.get_end(), Some(at(18).into()));
+        assert_eq!(event.get_location(), Some("BMU 210"));
+
+        // the tag wins over a wrong guess, and is exact without a guild zone
+        let mut output: Extraction = serde_json::from_str(
+            r#"{"events": [{"title": "game night", "date": "+1", "starttime": "1900"}]}"#,
+        )
+        .unwrap();
+        let stamps = timestamps::resolve("<t:1738800000:R>", chrono_tz::UTC).stamps;
+        output.pin_timestamps(&stamps, chrono_tz::UTC, &date);
+        let calendar = output.to_ical(&date, None).unwrap();
+        let event = calendar.components.first().unwrap().as_event().unwrap();
+        assert_eq!(
+            event.get_start(),
+            Some(CalendarDateTime::Utc(Utc.timestamp_opt(1738800000, 0).unwrap()).into())
+        );
+    }
+
+    #[tokio::test]
+    async fn overrides() {
+        let date = NaiveDate::from_ymd_opt(2025, 3, 1).unwrap();
+        let overrides = Overrides {
+            date: Some("x0314".to_owned()),
+            starttime: Some("1700".to_owned()),
+            endtime: Some("1900".to_owned()),
+            location: Some("Acker Gym".to_owned()),
+        };
+        let calendar = parse_msg_with(
+            None,
+            "Career fair tomorrow 10am-2pm in OCNL 241",
+            &date,
+            None,
+            &overrides,
+        )
+        .await
+        .unwrap();
+        let event = calendar.components.first().unwrap().as_event().unwrap();
+        let day = NaiveDate::from_ymd_opt(2025, 3, 14).unwrap();
+        assert_eq!(
+            event.get_start(),
+            Some(day.and_hms_opt(17, 0, 0).unwrap().into())
+        );
+        assert_eq!(
+            event.get_end(),
+            Some(day.and_hms_opt(19, 0, 0).unwrap().into())
+        );
+        assert_eq!(event.get_location(), Some("Acker Gym"));
+
+        // nothing to extract, but the date is enough
+        let calendar = parse_msg_with(None, "ACM general meeting", &date, None, &overrides)
+            .await
+            .unwrap();
+        let event = calendar.components.first().unwrap().as_event().unwrap();
+        assert_eq!(event.get_summary(), Some("ACM General Meeting"));
+    }
+
+    #[test]
+    fn edit_keeps_zone_span_and_recurrence() {
+        let output: Extraction = serde_json::from_str(
+            r#"{"events": [
+                {"title": "cybersecurity club", "date": "x0303", "starttime": "1700",
+                 "endtime": "1800", "timezone": "PST", "location": "OCNL 239",
+                 "recurrence": {"frequency": "weekly", "weekdays": ["mon"],
+                                "until": "x0505", "exceptions": ["x0317"]}},
+                {"title": "hackathon", "date": "x0307", "enddate": "x0309"}
+            ]}"#,
+        )
+        .unwrap();
+        let today = NaiveDate::from_ymd_opt(2025, 3, 1).unwrap();
+        let calendar = output.to_ical(&today, None).unwrap();
+        let events: Vec<_> = calendar
+            .components
+            .iter()
+            .filter_map(|c| c.as_event())
+            .collect();
+
+        let edit = EventEdit {
+            title: "iOS Club".to_owned(),
+            date: "x0304".to_owned(),
+            starttime: Some("1800".to_owned()),
+            endtime: Some("1900".to_owned()),
+            location: None,
+            description: Some("Bring a laptop".to_owned()),
+        };
+        let edited = edit_event(events[0], &edit, &today).unwrap();
+        assert_eq!(edited.get_summary(), Some("iOS Club"));
+        assert_eq!(edited.get_uid(), events[0].get_uid());
+        assert_eq!(edited.get_location(), None);
+        assert_eq!(edited.get_description(), Some("Bring a laptop"));
+        assert_eq!(
+            edited.get_start(),
+            Some(
+                CalendarDateTime::WithTimezone {
+                    date_time: NaiveDate::from_ymd_opt(2025, 3, 4)
+                        .unwrap()
+                        .and_hms_opt(18, 0, 0)
+                        .unwrap(),
+                    tzid: "America/Los_Angeles".to_owned(),
+                }
+                .into()
+            )
+        );
+        assert_eq!(
+            edited.property_value("RRULE"),
+            Some("FREQ=WEEKLY;BYDAY=MO;UNTIL=20250506T065959Z")
+        );
+        let exdates: Vec<_> = edited.multi_properties()["EXDATE"]
+            .iter()
+            .map(|p| p.value())
+            .collect();
+        assert_eq!(exdates, ["20250317T180000"]);
+
+        // a three day event moved by a week still takes three days
+        let edit = EventEdit {
+            title: "Hackathon".to_owned(),
+            date: "x0314".to_owned(),
+            starttime: None,
+            endtime: None,
+            location: None,
+            description: None,
+        };
+        let edited = edit_event(events[1], &edit, &today).unwrap();
+        let day = |d| NaiveDate::from_ymd_opt(2025, 3, d).unwrap();
+        assert_eq!(edited.get_start(), Some(day(14).into()));
+        assert_eq!(edited.get_end(), Some(day(17).into()));
+    }
+
+    #[test]
+    fn to_ical_multiple_events() {
+        let output: Extraction = serde_json::from_str(
+            r#"{"events": [
+                {"title": "career talk", "date": "_thu", "starttime": "1700",
+                 "endtime": "1700", "location": "OCNL 241"},
+                {"title": "graphics division", "date": "_wed", "starttime": "1800",
+                 "endtime": "1900", "location": "OCNL 239"},
+                {"title": "bogus", "date": "soon", "starttime": "1800",
+                 "endtime": "1900", "location": null}
+            ]}"#,
+        )
+        .unwrap();
+        let msg_date = NaiveDate::from_ymd_opt(2025, 2, 3).unwrap();
+        let calendar = output.to_ical(&msg_date, None).unwrap();
+
+        let titles: Vec<_> = calendar
+            .components
+            .iter()
+            .filter_map(|c| c.as_event())
+            .map(|e| e.get_summary().unwrap())
+            .collect();
+        assert_eq!(titles, ["Career Talk", "Graphics Division"]);
+    }
+
+    #[test]
+    fn to_ical_weekly_recurrence() {
+        let output: Extraction = serde_json::from_str(
+            r#"{"events": [
+                {"title": "cybersecurity club", "date": "_mon", "starttime": "1700",
+                 "endtime": "1800", "location": "OCNL 239",
+                 "recurrence": {"frequency": "weekly", "interval": null, "weekdays": ["mon"],
+                                "until": "x0505", "count": null, "exceptions": ["x0317"]}}
+            ]}"#,
+        )
+        .unwrap();
+        let msg_date = NaiveDate::from_ymd_opt(2025, 1, 28).unwrap();
+        let calendar = output.to_ical(&msg_date, None).unwrap();
+        let event = calendar.components.first().unwrap().as_event().unwrap();
+
+        assert_eq!(
+            event.property_value("RRULE"),
+            Some("FREQ=WEEKLY;BYDAY=MO;UNTIL=20250505T235959")
+        );
+        let exdates: Vec<_> = event.multi_properties()["EXDATE"]
+            .iter()
+            .map(|p| p.value())
+            .collect();
+        assert_eq!(exdates, ["20250317T170000"]);
+    }
+
+    #[test]
+    fn to_ical_all_day_multi_day() {
+        let output: Extraction = serde_json::from_str(
+            r#"{"events": [
+                {"title": "hackathon", "date": "x0307", "enddate": "x0309", "location": "BMU"},
+                {"title": "career fair", "date": "x0312", "starttime": "",
+                 "location": "Acker Gym"},
+                {"title": "game jam", "date": "x0314", "enddate": "x0316",
+                 "starttime": "1700", "endtime": "1200", "location": "OCNL 241"}
+            ]}"#,
+        )
+        .unwrap();
+        let msg_date = NaiveDate::from_ymd_opt(2025, 3, 1).unwrap();
+        let calendar = output.to_ical(&msg_date, None).unwrap();
+        let events: Vec<_> = calendar
+            .components
+            .iter()
+            .filter_map(|c| c.as_event())
+            .collect();
+        let day = |d| NaiveDate::from_ymd_opt(2025, 3, d).unwrap();
+
+        assert_eq!(events[0].get_start(), Some(day(7).into()));
+        assert_eq!(events[0].get_end(), Some(day(10).into()));
+        assert_eq!(events[1].get_start(), Some(day(12).into()));
+        assert_eq!(events[1].get_end(), Some(day(13).into()));
+        assert_eq!(
+            events[2].get_start(),
+            Some(day(14).and_hms_opt(17, 0, 0).unwrap().into())
+        );
+        assert_eq!(
+            events[2].get_end(),
+            Some(day(16).and_hms_opt(12, 0, 0).unwrap().into())
+        );
+        assert!(events[0]
+            .to_string()
+            .contains("DTSTART;VALUE=DATE:20250307"));
+    }
+
+    #[test]
+    fn to_ical_timezones() {
+        let output: Extraction = serde_json::from_str(
+            r#"{"events": [
+                {"title": "guild default", "date": "+1", "starttime": "1700",
+                 "endtime": "1800", "location": "OCNL 241"},
+                {"title": "explicit utc", "date": "+1", "starttime": "1700",
+                 "endtime": "1800", "timezone": "UTC", "location": "Online"},
+                {"title": "explicit eastern", "date": "+1", "starttime": "1700",
+                 "endtime": "1800", "timezone": "EST", "location": "Online"}
+            ]}"#,
+        )
+        .unwrap();
+        let msg_date = NaiveDate::from_ymd_opt(2025, 3, 13).unwrap();
+        let calendar = output
+            .to_ical(&msg_date, Some(chrono_tz::America::Los_Angeles))
+            .unwrap();
+        let starts: Vec<_> = calendar
+            .components
+            .iter()
+            .filter_map(|c| c.as_event())
+            .map(|e| e.get_start().unwrap())
+            .collect();
+        let five_pm = NaiveDate::from_ymd_opt(2025, 3, 14)
+            .unwrap()
+            .and_hms_opt(17, 0, 0)
+            .unwrap();
+
+        assert_eq!(
+            starts,
+            [
+                CalendarDateTime::WithTimezone {
+                    date_time: five_pm,
+                    tzid: "America/Los_Angeles".to_owned()
+                }
+                .into(),
+                CalendarDateTime::Utc(five_pm.and_utc()).into(),
+                CalendarDateTime::WithTimezone {
+                    date_time: five_pm,
+                    tzid: "America/New_York".to_owned()
+                }
+                .into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn to_ical_crosses_midnight() {
+        let output: Extraction = serde_json::from_str(
+            r#"{"events": [
+                {"title": "lan party", "date": "x0314", "starttime": "2200",
+                 "endtime": "0200", "location": "OCNL 241"}
+            ]}"#,
+        )
+        .unwrap();
+        let msg_date = NaiveDate::from_ymd_opt(2025, 3, 1).unwrap();
+        let calendar = output.to_ical(&msg_date, None).unwrap();
+        let event = calendar.components.first().unwrap().as_event().unwrap();
+
+        assert_eq!(
+            event.get_end(),
+            Some(
+                NaiveDate::from_ymd_opt(2025, 3, 15)
+                    .unwrap()
+                    .and_hms_opt(2, 0, 0)
+                    .unwrap()
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn to_ical_invalid_duration() {
+        let output: Extraction = serde_json::from_str(
+            r#"{"events": [
+                {"title": "backwards", "date": "x0314", "enddate": "x0312",
+                 "location": "OCNL 241"},
+                {"title": "forever", "date": "x0314", "enddate": "x0614",
+                 "starttime": "0900", "endtime": "1700", "location": "OCNL 241"}
+            ]}"#,
+        )
+        .unwrap();
+        let msg_date = NaiveDate::from_ymd_opt(2025, 3, 1).unwrap();
+
+        assert!(matches!(
+            output.events[0].to_event(&msg_date, None),
+            Err(Error::InvalidDuration { start, end }) if end < start
+        ));
+        assert!(matches!(
+            output.events[1].to_event(&msg_date, None),
+            Err(Error::InvalidDuration { start, end }) if end > start
+        ));
+        assert!(matches!(
+            output.to_ical(&msg_date, None),
+            Err(Error::InvalidDuration { .. })
+        ));
+    }
+
+    #[test]
+    fn parse_output_field_errors() {
+        let missing_date = r#"{"events": [{"title": "acm meeting", "starttime": "1600"}]}"#;
+        assert!(matches!(
+            parse_output(missing_date),
+            Err(Error::InvalidOutput { field, reason })
+                if field == "events[0]" && reason.contains("date")
+        ));
+
+        let bad_count = r#"{"events": [{"title": "acm meeting", "date": "+1",
+            "recurrence": {"frequency": "weekly", "count": "ten"}}]}"#;
+        assert!(matches!(
+            parse_output(bad_count),
+            Err(Error::InvalidOutput { field, .. }) if field == "events[0].recurrence.count"
+        ));
+
+        assert!(matches!(parse_output(""), Err(Error::ParseFailure)));
+        assert!(parse_output("```json\n{\"events\": []}\n```").is_ok());
+    }
+
+    #[test]
+    fn to_ical_invalid_time_field() {
+        let output: Extraction = serde_json::from_str(
+            r#"{"events": [
+                {"title": "acm meeting", "date": "+1", "starttime": "4pm", "endtime": "1800"}
+            ]}"#,
+        )
+        .unwrap();
+        let msg_date = NaiveDate::from_ymd_opt(2025, 3, 1).unwrap();
+
+        assert!(matches!(
+            output.to_ical(&msg_date, None),
+            Err(Error::InvalidOutput { field, .. }) if field == "events[0].starttime"
+        ));
+    }
+
+    #[test]
+    fn output_containing_failed() {
+        // any reply containing "failed" used to be treated as the model giving up
+        let output = parse_output(
+            r#"{"events": [{"title": "Failed Startups Panel", "date": "+1", "enddate": null,
+                "starttime": "1800", "endtime": "1900", "timezone": null, "location": null,
+                "description": "Founders talk about what went wrong", "recurrence": null},
+                {"title": "Mixer", "date": "+1", "enddate": null, "starttime": null,
+                "endtime": null, "timezone": null, "location": "Failed Startups Panel",
+                "description": null, "recurrence": null}]}"#,
+        )
+        .unwrap();
+        let msg_date = NaiveDate::from_ymd_opt(2025, 3, 1).unwrap();
+        let calendar = output.to_ical(&msg_date, None).unwrap();
+        let event = calendar.components.first().unwrap().as_event().unwrap();
+
+        assert_eq!(event.get_summary(), Some("Failed Startups Panel"));
+        assert_eq!(event.get_location(), None);
+        let mixer = calendar.components[1].as_event().unwrap();
+        assert_eq!(mixer.get_location(), Some("Failed Startups Panel"));
+    }
+
+    #[test]
+    fn schema_matches_model() {
+        let schema: serde_json::Value = serde_json::from_str(OUTPUT_SCHEMA).unwrap();
+        let event = &schema["properties"]["events"]["items"];
+        let required: Vec<_> = event["required"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|k| k.as_str().unwrap())
+            .collect();
+        assert_eq!(
+            required,
+            [
+                "title",
+                "date",
+                "enddate",
+                "starttime",
+                "endtime",
+                "timezone",
+                "location",
+                "description",
+                "recurrence"
+            ]
+        );
+
+        // an object with every required key set must deserialize
+        let mut full = serde_json::Map::new();
+        for key in required {
+            full.insert(key.to_owned(), serde_json::Value::Null);
+        }
+        full["title"] = "t".into();
+        full["date"] = "+0".into();
+        let extraction = serde_json::json!({ "events": [full] });
+        assert!(serde_json::from_value::<Extraction>(extraction).is_ok());
+    }
+
+    // by default, ignore tests that require a POST request to a real LLM
 
     #[tokio::test]
     #[ignore]
     async fn mock_irrelevant_input() {
         let msg = "69420";
-        let res = parse_msg(msg, &Local::now().date_naive()).await;
+        let res = parse_msg(
+            backend::from_env().as_deref(),
+            msg,
+            &Local::now().date_naive(),
+            None,
+        )
+        .await;
         assert!(matches!(res, Err(Error::ParseFailure)));
     }
 
@@ -250,7 +1396,7 @@ mod tests {
     async fn mock_today_date() {
         let msg = "ACM Club is meeting today from 4-6pm in OCNL 241!";
         let date = Local::now().date_naive();
-        let res = parse_msg(msg, &date).await;
+        let res = parse_msg(backend::from_env().as_deref(), msg, &date, None).await;
 
         assert!(res.is_ok());
         let calendar = res.unwrap();
@@ -299,7 +1445,7 @@ mod tests {
     async fn mock_tmrw_historical_leap_year() {
         let msg = "ACM Club is meeting tomorrow from 4-6pm in OCNL 241!";
         let date = NaiveDate::from_ymd_opt(2024, 2, 28).unwrap();
-        let res = parse_msg(msg, &date).await;
+        let res = parse_msg(backend::from_env().as_deref(), msg, &date, None).await;
 
         assert!(res.is_ok());
         let calendar = res.unwrap();
@@ -348,7 +1494,13 @@ mod tests {
     async fn mock_2_days_historical_leap_year() {
         let msg = "ACM Club is meeting in two days from 4-6pm in OCNL 241!";
         let final_date = NaiveDate::from_ymd_opt(2020, 2, 29).unwrap();
-        let res = parse_msg(msg, &final_date.checked_sub_days(Days::new(2)).unwrap()).await;
+        let res = parse_msg(
+            backend::from_env().as_deref(),
+            msg,
+            &final_date.checked_sub_days(Days::new(2)).unwrap(),
+            None,
+        )
+        .await;
 
         assert!(res.is_ok());
         let calendar = res.unwrap();
@@ -397,7 +1549,13 @@ mod tests {
     async fn mock_5_days_historical_leap_year() {
         let msg = "ACM Club is meeting in five days from 5-7pm in OCNL 241!";
         let final_date = NaiveDate::from_ymd_opt(2020, 2, 29).unwrap();
-        let res = parse_msg(msg, &final_date.checked_sub_days(Days::new(5)).unwrap()).await;
+        let res = parse_msg(
+            backend::from_env().as_deref(),
+            msg,
+            &final_date.checked_sub_days(Days::new(5)).unwrap(),
+            None,
+        )
+        .await;
 
         assert!(res.is_ok());
         let calendar = res.unwrap();
@@ -446,7 +1604,7 @@ mod tests {
     async fn mock_missing_end_time() {
         let msg = "ACM Club is meeting in tomorrow at 4pm in OCNL 241!";
         let date = NaiveDate::from_ymd_opt(2021, 6, 9).unwrap();
-        let res = parse_msg(msg, &date).await;
+        let res = parse_msg(backend::from_env().as_deref(), msg, &date, None).await;
 
         assert!(res.is_ok());
         let calendar = res.unwrap();
@@ -498,7 +1656,7 @@ mod tests {
     async fn mock_exact_date() {
         let msg = "ACM Club is meeting on 10/31 from 11:30-2:45pm in the Mechoopda Dorms";
         let date = NaiveDate::from_ymd_opt(2009, 6, 9).unwrap();
-        let res = parse_msg(msg, &date).await;
+        let res = parse_msg(backend::from_env().as_deref(), msg, &date, None).await;
 
         assert!(res.is_ok());
         let calendar = res.unwrap();
@@ -556,13 +1714,26 @@ mod tests {
     async fn real_usr0_1_28_25() {
         let msg = "Hey @everyone Voting has concluded and it has been decided that our meeting time this semester will be Mondays from 5-6 in OCNL 239.  Our first meeting will be next Monday where we will be discussing the schedule for the upcoming semester, and doing some intro into hacking and cybersecurity.";
         let date = NaiveDate::from_ymd_opt(2025, 1, 28).unwrap();
-        let res = parse_msg(msg, &date).await;
+        let res = parse_msg(backend::from_env().as_deref(), msg, &date, None).await;
 
         assert!(res.is_ok());
         let calendar = res.unwrap();
         assert_eq!(calendar.components.len(), 1);
         let event = calendar.components.first().unwrap().as_event().unwrap();
 
+        assert_eq!(
+            event
+                .property_value("RRULE")
+                .and_then(|rrule| RecurrenceRule::from_rrule(rrule, &event.get_start().unwrap())),
+            Some(RecurrenceRule {
+                frequency: Frequency::Weekly,
+                interval: 1,
+                weekdays: vec![chrono::Weekday::Mon],
+                until: None,
+                count: None,
+            })
+        );
+
         let location = event.get_location();
         assert!(location.is_some(), "Expected location to be present");
         assert_eq!(location.unwrap(), "OCNL 239");
@@ -614,11 +1785,11 @@ mod tests {
     async fn real_tpc_2_3_25() {
         let msg = "@everyone It is my great pleasure to announce TPC's first meeting of the semester! Join us this Thursday, at 5 PM in OCNL 241 for an inspiring talk on careers, life, and projects by *James Krepelka*, an experienced lecturer and software veteran of Amazon, Google, Palo Alto Networks, and more! See you there! Additionally, if you’re interested in graphics programming, TPC's Graphics Division is looking to find a time for its first meeting of the semester! No prior graphics experience required! Graphics Division will be meeting weekly on Wednesdays, starting next week. Please use the when2meet to help select a time! https://www.when2meet.com/?28823530-WUAPh";
         let msg_date = NaiveDate::from_ymd_opt(2025, 2, 3).unwrap();
-        let res = parse_msg(msg, &msg_date).await;
+        let res = parse_msg(backend::from_env().as_deref(), msg, &msg_date, None).await;
 
         assert!(res.is_ok());
         let calendar = res.unwrap();
-        assert_eq!(calendar.components.len(), 1);
+        assert_eq!(calendar.components.len(), 2);
         let event = calendar.components.first().unwrap().as_event().unwrap();
 
         let location = event.get_location();
@@ -652,11 +1823,11 @@ mod tests {
     async fn real_tpc_11_20_24() {
         let msg = "@everyone It is my great pleasure to announce TPC's first meeting of the semester! Join us this Thursday, at 5 PM in OCNL 241 for an inspiring talk on careers, life, and projects by *James Krepelka*, an experienced lecturer and software veteran of Amazon, Google, Palo Alto Networks, and more! See you there! Additionally, if you’re interested in graphics programming, TPC's Graphics Division is looking to find a time for its first meeting of the semester! No prior graphics experience required! Graphics Division will be meeting weekly on Wednesdays, starting next week. Please use the when2meet to help select a time! https://www.when2meet.com/?28823530-WUAPh";
         let msg_date = NaiveDate::from_ymd_opt(2024, 11, 20).unwrap();
-        let res = parse_msg(msg, &msg_date).await;
+        let res = parse_msg(backend::from_env().as_deref(), msg, &msg_date, None).await;
 
         assert!(res.is_ok());
         let calendar = res.unwrap();
-        assert_eq!(calendar.components.len(), 1);
+        assert_eq!(calendar.components.len(), 2);
         let event = calendar.components.first().unwrap().as_event().unwrap();
 
         let location = event.get_location();
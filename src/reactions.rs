@@ -0,0 +1,78 @@
+//! Converting a message by reacting to it, instead of replying with a mention.
+//!
+//! Reacting with the guild's emoji ([`DEFAULT_EMOJI`] unless changed with
+//! `/settings reaction_emoji:<emoji>`) goes through the same draft as a mention, see
+//! `Handler::reaction_add`. Only the first reaction on a message does anything.
+
+use serenity::all::ReactionType;
+
+pub const DEFAULT_EMOJI: &str = "📅";
+
+/// Emoji typed into `/settings`: a Unicode emoji, or a custom one like `<:calendar:1234>`.
+pub fn parse_emoji(input: &str) -> Option<String> {
+    let input = input.trim();
+    let valid = match ReactionType::try_from(input).ok()? {
+        ReactionType::Custom { .. } => true,
+        // anything short without letters, digits or spaces, which Discord will reject if it
+        // isn't an emoji
+        _ => {
+            input.chars().count() <= 8
+                && !input
+                    .chars()
+                    .any(|c| c.is_alphanumeric() || c.is_whitespace())
+        }
+    };
+    valid.then(|| input.to_owned())
+}
+
+/// Whether `reaction` is `emoji`. Custom emoji are compared by ID, since they can be renamed.
+pub fn matches(emoji: &str, reaction: &ReactionType) -> bool {
+    // some clients add a variation selector to emoji that have a text form
+    let plain = |s: &str| s.trim_end_matches('\u{fe0f}').to_owned();
+    match (ReactionType::try_from(emoji), reaction) {
+        (Ok(ReactionType::Custom { id, .. }), ReactionType::Custom { id: reacted, .. }) => {
+            id == *reacted
+        }
+        (Ok(ReactionType::Unicode(emoji)), ReactionType::Unicode(reacted)) => {
+            plain(&emoji) == plain(reacted)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::all::EmojiId;
+
+    use super::*;
+
+    #[test]
+    fn parses() {
+        assert_eq!(parse_emoji(" 🗓️ ").as_deref(), Some("🗓️"));
+        assert_eq!(
+            parse_emoji("<:calendar:1234>").as_deref(),
+            Some("<:calendar:1234>")
+        );
+        assert_eq!(parse_emoji("calendar"), None);
+        assert_eq!(parse_emoji(":calendar:"), None);
+        assert_eq!(parse_emoji("<:calendar>"), None);
+        assert_eq!(parse_emoji(""), None);
+    }
+
+    #[test]
+    fn matching() {
+        let unicode = |s: &str| ReactionType::Unicode(s.to_owned());
+        assert!(matches(DEFAULT_EMOJI, &unicode("📅")));
+        assert!(!matches(DEFAULT_EMOJI, &unicode("👍")));
+        assert!(matches("🗓️", &unicode("🗓")));
+
+        let custom = |id: u64, name: &str| ReactionType::Custom {
+            animated: false,
+            id: EmojiId::new(id),
+            name: Some(name.to_owned()),
+        };
+        assert!(matches("<:calendar:1234>", &custom(1234, "renamed")));
+        assert!(!matches("<:calendar:1234>", &custom(5678, "calendar")));
+        assert!(!matches(DEFAULT_EMOJI, &custom(1234, "calendar")));
+    }
+}
@@ -0,0 +1,294 @@
+use chrono::{NaiveDate, NaiveDateTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use icalendar::{CalendarDateTime, DatePerhapsTime};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+impl Frequency {
+    fn as_rrule(&self) -> &'static str {
+        match self {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+            Frequency::Yearly => "YEARLY",
+        }
+    }
+
+    fn unit(&self) -> &'static str {
+        match self {
+            Frequency::Daily => "day",
+            Frequency::Weekly => "week",
+            Frequency::Monthly => "month",
+            Frequency::Yearly => "year",
+        }
+    }
+}
+
+/// A resolved recurrence, convertible to and from an RFC 5545 RRULE value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecurrenceRule {
+    pub frequency: Frequency,
+    pub interval: u32,
+    pub weekdays: Vec<Weekday>,
+    /// Last day (inclusive) an occurrence can start on
+    pub until: Option<NaiveDate>,
+    pub count: Option<u32>,
+}
+
+pub fn parse_weekday(day: &str) -> Option<Weekday> {
+    match day.to_lowercase().get(..3)? {
+        "mon" => Some(Weekday::Mon),
+        "tue" => Some(Weekday::Tue),
+        "wed" => Some(Weekday::Wed),
+        "thu" => Some(Weekday::Thu),
+        "fri" => Some(Weekday::Fri),
+        "sat" => Some(Weekday::Sat),
+        "sun" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+fn weekday_code(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+fn weekday_name(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "Monday",
+        Weekday::Tue => "Tuesday",
+        Weekday::Wed => "Wednesday",
+        Weekday::Thu => "Thursday",
+        Weekday::Fri => "Friday",
+        Weekday::Sat => "Saturday",
+        Weekday::Sun => "Sunday",
+    }
+}
+
+fn zone_of(dtstart: &DatePerhapsTime) -> Option<Tz> {
+    match dtstart {
+        DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone { tzid, .. }) => tzid.parse().ok(),
+        _ => None,
+    }
+}
+
+fn format_until(until: NaiveDate, dtstart: &DatePerhapsTime) -> String {
+    let last_moment = until.and_hms_opt(23, 59, 59).unwrap();
+    match dtstart {
+        DatePerhapsTime::Date(_) => until.format("%Y%m%d").to_string(),
+        DatePerhapsTime::DateTime(CalendarDateTime::Floating(_)) => {
+            last_moment.format("%Y%m%dT%H%M%S").to_string()
+        }
+        // zoned events must give UNTIL in UTC
+        _ => {
+            let utc = match zone_of(dtstart) {
+                Some(tz) => tz
+                    .from_local_datetime(&last_moment)
+                    .earliest()
+                    .map(|dt| dt.naive_utc())
+                    .unwrap_or(last_moment),
+                None => last_moment,
+            };
+            utc.format("%Y%m%dT%H%M%SZ").to_string()
+        }
+    }
+}
+
+fn parse_until(val: &str, dtstart: &DatePerhapsTime) -> Option<NaiveDate> {
+    let Some(utc) = val.strip_suffix('Z') else {
+        return NaiveDate::parse_from_str(val.get(..8)?, "%Y%m%d").ok();
+    };
+    let utc = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok()?;
+    match zone_of(dtstart) {
+        Some(tz) => Some(tz.from_utc_datetime(&utc).date_naive()),
+        None => Some(Utc.from_utc_datetime(&utc).date_naive()),
+    }
+}
+
+impl RecurrenceRule {
+    /// `dtstart` is the event's start, since UNTIL has to use a value type matching it.
+    pub fn to_rrule(&self, dtstart: &DatePerhapsTime) -> String {
+        let mut parts = vec![format!("FREQ={}", self.frequency.as_rrule())];
+        if self.interval > 1 {
+            parts.push(format!("INTERVAL={}", self.interval));
+        }
+        if !self.weekdays.is_empty() {
+            let days: Vec<_> = self.weekdays.iter().map(|d| weekday_code(*d)).collect();
+            parts.push(format!("BYDAY={}", days.join(",")));
+        }
+        // UNTIL and COUNT are mutually exclusive, prefer the explicit end date
+        if let Some(until) = self.until {
+            parts.push(format!("UNTIL={}", format_until(until, dtstart)));
+        } else if let Some(count) = self.count {
+            parts.push(format!("COUNT={count}"));
+        }
+        parts.join(";")
+    }
+
+    /// Inverse of [`Self::to_rrule`], `dtstart` is needed to read UNTIL back in the event's zone.
+    pub fn from_rrule(rrule: &str, dtstart: &DatePerhapsTime) -> Option<Self> {
+        let mut rule = RecurrenceRule {
+            frequency: Frequency::Weekly,
+            interval: 1,
+            weekdays: Vec::new(),
+            until: None,
+            count: None,
+        };
+        let mut has_freq = false;
+
+        for part in rrule.split(';') {
+            let (key, val) = part.split_once('=')?;
+            match key {
+                "FREQ" => {
+                    has_freq = true;
+                    rule.frequency = match val {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => return None,
+                    }
+                }
+                "INTERVAL" => rule.interval = val.parse().ok()?,
+                "BYDAY" => {
+                    rule.weekdays = val
+                        .split(',')
+                        .map(|d| match d {
+                            "MO" => Some(Weekday::Mon),
+                            "TU" => Some(Weekday::Tue),
+                            "WE" => Some(Weekday::Wed),
+                            "TH" => Some(Weekday::Thu),
+                            "FR" => Some(Weekday::Fri),
+                            "SA" => Some(Weekday::Sat),
+                            "SU" => Some(Weekday::Sun),
+                            _ => None,
+                        })
+                        .collect::<Option<_>>()?
+                }
+                "UNTIL" => rule.until = parse_until(val, dtstart),
+                "COUNT" => rule.count = val.parse().ok(),
+                _ => {}
+            }
+        }
+
+        has_freq.then_some(rule)
+    }
+
+    /// Plain English summary, e.g. "Every 2 weeks on Monday and Wednesday, until Thu, May 1, 2025"
+    pub fn describe(&self) -> String {
+        let mut desc = if self.interval > 1 {
+            format!("Every {} {}s", self.interval, self.frequency.unit())
+        } else {
+            format!("Every {}", self.frequency.unit())
+        };
+
+        if !self.weekdays.is_empty() {
+            let names: Vec<_> = self.weekdays.iter().map(|d| weekday_name(*d)).collect();
+            let days = match names.split_last() {
+                Some((last, [])) => last.to_string(),
+                Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+                None => unreachable!(),
+            };
+            desc.push_str(" on ");
+            desc.push_str(&days);
+        }
+
+        if let Some(until) = self.until {
+            desc.push_str(&format!(", until {}", until.format("%a, %b %e, %Y")));
+        } else if let Some(count) = self.count {
+            desc.push_str(&format!(", {count} times"));
+        }
+        desc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rrule_round_trip() {
+        let rule = RecurrenceRule {
+            frequency: Frequency::Weekly,
+            interval: 2,
+            weekdays: vec![Weekday::Mon, Weekday::Wed],
+            until: NaiveDate::from_ymd_opt(2025, 5, 1),
+            count: None,
+        };
+        let floating = floating_start();
+        let rrule = rule.to_rrule(&floating);
+        assert_eq!(
+            rrule,
+            "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,WE;UNTIL=20250501T235959"
+        );
+        assert_eq!(RecurrenceRule::from_rrule(&rrule, &floating), Some(rule));
+    }
+
+    #[test]
+    fn rrule_until_zoned() {
+        let rule = RecurrenceRule {
+            frequency: Frequency::Weekly,
+            interval: 1,
+            weekdays: vec![],
+            until: NaiveDate::from_ymd_opt(2025, 5, 1),
+            count: None,
+        };
+        let zoned = DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone {
+            date_time: NaiveDate::from_ymd_opt(2025, 3, 3)
+                .unwrap()
+                .and_hms_opt(17, 0, 0)
+                .unwrap(),
+            tzid: "America/Los_Angeles".to_owned(),
+        });
+        let rrule = rule.to_rrule(&zoned);
+        assert_eq!(rrule, "FREQ=WEEKLY;UNTIL=20250502T065959Z");
+        assert_eq!(RecurrenceRule::from_rrule(&rrule, &zoned), Some(rule));
+    }
+
+    fn floating_start() -> DatePerhapsTime {
+        NaiveDate::from_ymd_opt(2025, 3, 3)
+            .unwrap()
+            .and_hms_opt(17, 0, 0)
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn describe_weekly() {
+        let rule = RecurrenceRule::from_rrule("FREQ=WEEKLY;BYDAY=MO", &floating_start()).unwrap();
+        assert_eq!(rule.describe(), "Every week on Monday");
+
+        let rule = RecurrenceRule::from_rrule(
+            "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,WE,FR;COUNT=6",
+            &floating_start(),
+        )
+        .unwrap();
+        assert_eq!(
+            rule.describe(),
+            "Every 2 weeks on Monday, Wednesday and Friday, 6 times"
+        );
+    }
+
+    #[test]
+    fn describe_until() {
+        let rule =
+            RecurrenceRule::from_rrule("FREQ=DAILY;UNTIL=20250501T235959", &floating_start())
+                .unwrap();
+        assert_eq!(rule.describe(), "Every day, until Thu, May  1, 2025");
+    }
+}
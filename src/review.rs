@@ -0,0 +1,379 @@
+//! Parsed events are posted as a draft with "Edit" and "Confirm" buttons, and only get their
+//! "Add to iCal" buttons once whoever asked for them has confirmed they're right.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+use chrono::Utc;
+use icalendar::{Calendar, Component, Event, EventLike};
+use serenity::all::{
+    ActionRowComponent, ComponentInteraction, Context, CreateActionRow, CreateButton,
+    CreateInputText, CreateInteractionResponse, CreateInteractionResponseFollowup,
+    CreateInteractionResponseMessage, CreateModal, InputTextStyle, MessageBuilder, MessageId,
+    ModalInteraction,
+};
+
+use crate::{
+    host::IcsHost,
+    parser::{edit_event, EventEdit},
+    rules::{date_phrase, time_range_phrase},
+    scheduled,
+    store::{Origin, Store},
+    timezone::guild_timezone,
+    utils::{
+        button_label, calendar_buttons, calendar_events, calendar_message, date_and_time,
+        error_reply, local_date, CalendarUrls,
+    },
+};
+
+const EDIT: &str = "review:edit:";
+const CONFIRM: &str = "review:confirm";
+const MODAL: &str = "review:modal:";
+
+pub struct Draft {
+    pub events: Vec<Event>,
+    pub urls: CalendarUrls,
+    /// Only its author can edit or confirm it
+    pub origin: Origin,
+    /// Whether confirming it also creates Discord scheduled events, see `scheduled`
+    pub discord_events: bool,
+}
+
+impl Draft {
+    pub fn new(
+        calendar: &Calendar,
+        urls: CalendarUrls,
+        origin: Origin,
+        discord_events: bool,
+    ) -> Self {
+        Self {
+            events: calendar_events(calendar).into_iter().cloned().collect(),
+            urls,
+            origin,
+            discord_events,
+        }
+    }
+
+    fn calendar(&self) -> Calendar {
+        let mut calendar = Calendar::new();
+        for event in &self.events {
+            calendar.push(event.clone());
+        }
+        calendar.done()
+    }
+}
+
+/// Drafts waiting to be confirmed, by the message showing them.
+///
+/// They're only kept in memory, so a draft's buttons stop working when the bot restarts.
+#[derive(Default)]
+pub struct Drafts {
+    drafts: Mutex<HashMap<MessageId, Draft>>,
+    /// Announcements being converted for a reaction, see [`Drafts::claim`].
+    converting: Mutex<HashSet<MessageId>>,
+}
+
+impl Drafts {
+    pub fn insert(&self, message: MessageId, draft: Draft) {
+        self.drafts.lock().unwrap().insert(message, draft);
+    }
+
+    fn remove(&self, message: MessageId) -> Option<Draft> {
+        self.drafts.lock().unwrap().remove(&message)
+    }
+
+    /// Claims an announcement for converting, unless it already has a draft waiting to be
+    /// confirmed or is being converted. It stays claimed until [`Drafts::release`], so reactions
+    /// coming in while the first one is still being converted don't make drafts of their own.
+    pub fn claim(&self, source: MessageId) -> bool {
+        let mut converting = self.converting.lock().unwrap();
+        let drafts = self.drafts.lock().unwrap();
+        if drafts.values().any(|d| d.origin.source == Some(source)) {
+            return false;
+        }
+        converting.insert(source)
+    }
+
+    pub fn release(&self, source: MessageId) {
+        self.converting.lock().unwrap().remove(&source);
+    }
+}
+
+/// The draft's text, the same as what's shown once it's confirmed.
+pub fn draft_content(calendar: &Calendar) -> String {
+    let mut cal_msg = MessageBuilder::new();
+    calendar_message(calendar, &mut cal_msg);
+    cal_msg.build()
+}
+
+/// What the bot's message says once its events are cancelled: why, and the events struck out.
+pub fn cancelled_content(calendar: &Calendar, reason: &str) -> String {
+    let events: Vec<_> = draft_content(calendar)
+        .lines()
+        .map(|line| match line.trim() {
+            "" => String::new(),
+            line => format!("~~{line}~~"),
+        })
+        .collect();
+    format!("**Cancelled**, {reason}.\n{}", events.join("\n"))
+}
+
+/// "Edit" (or "Edit <title>" per event when there are several) and "Confirm", five to a row.
+pub fn draft_buttons(events: &[Event]) -> Vec<CreateActionRow> {
+    let mut buttons: Vec<_> = events
+        .iter()
+        .enumerate()
+        .map(|(i, event)| {
+            let label = match events.len() {
+                1 => "Edit".to_owned(),
+                _ => button_label("Edit", event.get_summary().unwrap_or("Event")),
+            };
+            CreateButton::new(format!("{EDIT}{i}")).label(label)
+        })
+        .collect();
+    buttons.push(CreateButton::new(CONFIRM).label("Confirm"));
+    buttons
+        .chunks(5)
+        .map(|row| CreateActionRow::Buttons(row.to_vec()))
+        .collect()
+}
+
+/// Handles the "Edit" and "Confirm" buttons of a draft. Confirmed events are recorded in `store`,
+/// and become Discord scheduled events if the draft says so.
+pub async fn button(ctx: &Context, drafts: &Drafts, store: &Store, comp: &ComponentInteraction) {
+    let id = comp.data.custom_id.as_str();
+    let message_id = comp.message.id;
+    let Some(draft) = drafts.remove(message_id) else {
+        respond_ephemeral(
+            ctx,
+            comp,
+            "Sorry! This draft has expired, please ask again.",
+        )
+        .await;
+        return;
+    };
+    if draft.origin.author != comp.user.id {
+        let author = draft.origin.author;
+        drafts.insert(message_id, draft);
+        respond_ephemeral(
+            ctx,
+            comp,
+            format!("Sorry! Only <@{author}> can change this event."),
+        )
+        .await;
+        return;
+    }
+
+    let response = if id == CONFIRM {
+        if let Err(why) = store.record(&draft.origin, message_id, &draft.events, &draft.urls) {
+            println!("Error recording events: {why}");
+        }
+        let calendar = draft.calendar();
+        let response = CreateInteractionResponse::UpdateMessage(
+            CreateInteractionResponseMessage::new()
+                .content(draft_content(&calendar))
+                .components(calendar_buttons(&calendar, draft.urls)),
+        );
+        comp.respond(ctx, response).await;
+
+        // after responding, since Discord only waits 3 seconds for that
+        let Some(guild_id) = draft.origin.guild_id.filter(|_| draft.discord_events) else {
+            return;
+        };
+        if let Err(why) = scheduled::sync(ctx, store, guild_id, &draft.events, &[]).await {
+            println!("Error creating scheduled events: {why}");
+            let followup = CreateInteractionResponseFollowup::new()
+                .content(
+                    "Sorry! I couldn't create the Discord event. Check that I have the \
+                     \"Create Events\" permission.",
+                )
+                .ephemeral(true);
+            if let Err(why) = comp.create_followup(ctx, followup).await {
+                println!("Error sending followup: {why}");
+            }
+        }
+        return;
+    } else if let Some((i, event)) = id
+        .strip_prefix(EDIT)
+        .and_then(|i| i.parse::<usize>().ok())
+        .and_then(|i| Some((i, draft.events.get(i)?)))
+    {
+        let modal = edit_modal(i, event);
+        drafts.insert(message_id, draft);
+        CreateInteractionResponse::Modal(modal)
+    } else {
+        drafts.insert(message_id, draft);
+        println!("Unknown draft button: {id}");
+        return;
+    };
+    comp.respond(ctx, response).await;
+}
+
+/// Anything a draft can be interacted with through.
+trait Respond {
+    async fn respond(&self, ctx: &Context, response: CreateInteractionResponse);
+}
+
+impl Respond for ComponentInteraction {
+    async fn respond(&self, ctx: &Context, response: CreateInteractionResponse) {
+        if let Err(why) = self.create_response(ctx, response).await {
+            println!("Error sending response: {why}");
+        }
+    }
+}
+
+impl Respond for ModalInteraction {
+    async fn respond(&self, ctx: &Context, response: CreateInteractionResponse) {
+        if let Err(why) = self.create_response(ctx, response).await {
+            println!("Error sending response: {why}");
+        }
+    }
+}
+
+async fn respond_ephemeral(ctx: &Context, to: &impl Respond, content: impl Into<String>) {
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(content)
+            .ephemeral(true),
+    );
+    to.respond(ctx, response).await;
+}
+
+/// A form with the event's current title, date, time, location and description. Discord allows
+/// five inputs per modal, so the start and end share the time input.
+fn edit_modal(i: usize, event: &Event) -> CreateModal {
+    let start = event.get_start().map(date_and_time);
+    let end = event.get_end().map(date_and_time);
+    let date = start.map(|(date, _)| date.format("%Y-%m-%d").to_string());
+    let time = match (start.and_then(|s| s.1), end.and_then(|e| e.1)) {
+        (Some(start), Some(end)) => format!(
+            "{} - {}",
+            start.format("%-I:%M %p"),
+            end.format("%-I:%M %p")
+        ),
+        (Some(start), None) => start.format("%-I:%M %p").to_string(),
+        _ => "All day".to_owned(),
+    };
+
+    let input = |label: &str, id: &str, value: Option<String>| {
+        let mut input = CreateInputText::new(InputTextStyle::Short, label, id).required(false);
+        if let Some(value) = value.filter(|v| !v.is_empty()) {
+            input = input.value(value);
+        }
+        CreateActionRow::InputText(input)
+    };
+    let mut description =
+        CreateInputText::new(InputTextStyle::Paragraph, "Description", "description")
+            .required(false);
+    if let Some(desc) = event.get_description().filter(|d| !d.is_empty()) {
+        description = description.value(desc);
+    }
+    CreateModal::new(format!("{MODAL}{i}"), "Edit event").components(vec![
+        input("Title", "title", event.get_summary().map(str::to_owned)),
+        input("Date", "date", date),
+        input("Time", "time", Some(time)),
+        input(
+            "Location",
+            "location",
+            event.get_location().map(str::to_owned),
+        ),
+        CreateActionRow::InputText(description),
+    ])
+}
+
+/// Reads the submitted form into an edit, or says which input couldn't be read.
+fn read_form(modal: &ModalInteraction) -> Result<EventEdit, String> {
+    let mut fields = HashMap::new();
+    for row in &modal.data.components {
+        for component in &row.components {
+            if let ActionRowComponent::InputText(input) = component {
+                let value = input.value.clone().unwrap_or_default();
+                fields.insert(input.custom_id.as_str(), value.trim().to_owned());
+            }
+        }
+    }
+    let field = |id: &str| fields.get(id).cloned().filter(|v| !v.is_empty());
+
+    let title = field("title").ok_or("The title can't be empty.")?;
+    let date = field("date").unwrap_or_default();
+    let date = date_phrase(&date).ok_or_else(|| {
+        format!("Sorry! I couldn't read the date `{date}`, try something like \"2025-09-21\".")
+    })?;
+    let time = field("time").unwrap_or_default();
+    let times = time_range_phrase(&time).ok_or_else(|| {
+        format!("Sorry! I couldn't read the time `{time}`, try something like \"4-6pm\".")
+    })?;
+    let (starttime, endtime) = times.unzip();
+    Ok(EventEdit {
+        title,
+        date,
+        starttime,
+        endtime,
+        location: field("location"),
+        description: field("description"),
+    })
+}
+
+/// Applies a submitted edit form, re-uploads the .ics files and updates the draft in place.
+pub async fn submit(ctx: &Context, drafts: &Drafts, host: &dyn IcsHost, modal: &ModalInteraction) {
+    let Some(message) = &modal.message else {
+        return;
+    };
+    let Some(i) = modal
+        .data
+        .custom_id
+        .strip_prefix(MODAL)
+        .and_then(|i| i.parse::<usize>().ok())
+    else {
+        println!("Unknown modal: {}", modal.data.custom_id);
+        return;
+    };
+    let Some(mut draft) = drafts.remove(message.id) else {
+        respond_ephemeral(
+            ctx,
+            modal,
+            "Sorry! This draft has expired, please ask again.",
+        )
+        .await;
+        return;
+    };
+
+    let today = local_date(Utc::now(), guild_timezone(modal.guild_id));
+    let edited = read_form(modal).and_then(|edit| {
+        let old = draft.events.get(i).ok_or("Sorry! That event is gone.")?;
+        edit_event(old, &edit, &today).map_err(|e| {
+            error_reply(&e).unwrap_or_else(|| "Sorry! Something went wrong.".to_owned())
+        })
+    });
+    let event = match edited {
+        Ok(event) => event,
+        Err(reason) => {
+            drafts.insert(message.id, draft);
+            respond_ephemeral(ctx, modal, reason).await;
+            return;
+        }
+    };
+
+    let old = std::mem::replace(&mut draft.events[i], event);
+    let calendar = draft.calendar();
+    match host.publish(ctx, &calendar).await {
+        Ok(urls) => draft.urls = urls,
+        Err(e) => {
+            draft.events[i] = old;
+            drafts.insert(message.id, draft);
+            let reply =
+                error_reply(&e).unwrap_or_else(|| "Sorry! Something went wrong.".to_owned());
+            respond_ephemeral(ctx, modal, reply).await;
+            return;
+        }
+    }
+    let response = CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::new()
+            .content(draft_content(&calendar))
+            .components(draft_buttons(&draft.events)),
+    );
+    drafts.insert(message.id, draft);
+    modal.respond(ctx, response).await;
+}
@@ -0,0 +1,587 @@
+//! Offline extraction for announcements simple enough not to need an LLM, e.g.
+//! "ACM meeting tomorrow 4-6pm in OCNL 241".
+//!
+//! Dates are written in the same grammar the LLM uses (see `date_grammar`), so a rule-based
+//! [`Extraction`] goes through the exact same conversion as the model's.
+
+use std::sync::LazyLock;
+
+use chrono::{NaiveDate, NaiveTime};
+use regex::{Captures, Regex};
+
+use crate::{
+    parser::{ExtractedEvent, Extraction},
+    timezone,
+};
+
+/// Titles longer than this are probably a sentence the rules didn't understand.
+const MAX_TITLE_WORDS: usize = 6;
+/// Longer messages tend to hold details (several events, exceptions, ...) the rules would miss.
+const MAX_SIMPLE_LEN: usize = 160;
+
+static MENTION: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<(?:@[!&]?|#)\d+>").unwrap());
+static DAY_WORD: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)\b(today|tonight|tomorrow|tmrw|tmr)\b").unwrap());
+static WEEKDAY: LazyLock<Regex> = LazyLock::new(|| {
+    // abbreviations have to be capitalized so "sat" and "sun" aren't read as weekdays
+    Regex::new(
+        r"\b(?:(?i:(?:this|next|on)\s+))?((?i:monday|tuesday|wednesday|thursday|friday|saturday|sunday)|Mon|Tues?|Wed|Thu(?:rs?)?|Fri|Sat|Sun)\b\.?",
+    )
+    .unwrap()
+});
+static ISO_DATE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b(\d{4})-(\d{1,2})-(\d{1,2})\b").unwrap());
+static SLASH_DATE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b(\d{1,2})/(\d{1,2})(?:/(\d{4}|\d{2}))?\b").unwrap());
+static TIME_RANGE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)\b(\d{1,2})(?::(\d{2}))?\s*(?:([ap])\.?m\b\.?)?\s*(?:-|–|—|to|until|till)\s*(\d{1,2})(?::(\d{2}))?\s*(?:([ap])\.?m\b\.?)?",
+    )
+    .unwrap()
+});
+static SINGLE_TIME: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b(\d{1,2})(?::(\d{2}))?\s*([ap])\.?m\b\.?|\b(\d{1,2}):(\d{2})\b|\b(noon)\b")
+        .unwrap()
+});
+static ZONE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b(UTC|GMT|[A-Z]{1,3}[SD]?T)\b").unwrap());
+static LOCATION: LazyLock<Regex> = LazyLock::new(|| {
+    // voice channels are written out by `mentions::channel_name`
+    Regex::new(r"(?:\b(?i:in|at)|@)\s+([\w-]+ (?:voice channel|stage)\b|[A-Z][\w.&'-]*(?:[ \t]+(?:[A-Z][\w.&'-]*|\d+[A-Za-z]?))*)")
+        .unwrap()
+});
+static RECURRING: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b(every|each|weekly|biweekly|daily|monthly)\b|\b\w+days\b").unwrap()
+});
+
+const MONTHS: [&str; 12] = [
+    "january",
+    "february",
+    "march",
+    "april",
+    "may",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confidence {
+    /// Everything in the message was understood, the LLM can be skipped
+    High,
+    /// Something was found, but the LLM should have a look if there is one
+    Low,
+}
+
+pub struct RuleExtraction {
+    pub extraction: Extraction,
+    pub confidence: Confidence,
+}
+
+/// Parts of the message that have been read, blanked out so later rules don't match them again
+/// and whatever is left over can be used as the title.
+struct Masked {
+    text: String,
+}
+
+impl Masked {
+    fn blank(&mut self, start: usize, end: usize) {
+        // same byte length, so every offset into the original message stays valid
+        self.text
+            .replace_range(start..end, &"|".repeat(end - start));
+    }
+
+    /// Every match of `re` in what's left, blanking each one.
+    fn take<T>(&mut self, re: &Regex, mut read: impl FnMut(&Captures) -> Option<T>) -> Vec<T> {
+        let mut found = Vec::new();
+        let mut spans = Vec::new();
+        for caps in re.captures_iter(&self.text) {
+            if let Some(value) = read(&caps) {
+                let span = caps.get(0).unwrap();
+                spans.push((span.start(), span.end()));
+                found.push(value);
+            }
+        }
+        for (start, end) in spans {
+            self.blank(start, end);
+        }
+        found
+    }
+}
+
+/// Reads the event out of `msg`, or `None` if it doesn't even have a date.
+///
+/// Only a single event is extracted. Messages announcing more than one, or with anything else
+/// the rules can't be sure about, are [`Confidence::Low`].
+pub fn extract(msg: &str) -> Option<RuleExtraction> {
+    let mut masked = Masked {
+        text: msg.to_owned(),
+    };
+    masked.take(&MENTION, |_| Some(()));
+    let mut confident = true;
+
+    // before times, so "09-21" in "2025-09-21" isn't read as a time range
+    let mut dates = masked.take(&ISO_DATE, iso_date);
+    dates.extend(masked.take(&SLASH_DATE, slash_date));
+    dates.extend(masked.take(&DAY_WORD, day_word));
+    dates.extend(masked.take(&WEEKDAY, weekday));
+    dates.dedup();
+    let date = dates.first()?.clone();
+    if dates.len() > 1 {
+        // several events, or a multi-day one
+        confident = false;
+    }
+
+    let mut times = masked.take(&TIME_RANGE, time_range);
+    times.extend(masked.take(&SINGLE_TIME, |caps| {
+        let time = single_time(caps)?;
+        Some((time, time))
+    }));
+    let (starttime, endtime) = match times.as_slice() {
+        [] => (None, None),
+        [((start, start_sure), (end, end_sure)), rest @ ..] => {
+            confident &= rest.is_empty() && *start_sure && *end_sure;
+            (Some(fmt_time(*start)), Some(fmt_time(*end)))
+        }
+    };
+    if starttime.is_none() {
+        // an all-day event, or times the rules can't read
+        confident = false;
+    }
+
+    let timezone = masked
+        .take(&ZONE, |caps| {
+            timezone::parse_zone(&caps[1]).map(|_| caps[1].to_owned())
+        })
+        .into_iter()
+        .next();
+
+    let location = masked
+        .take(&LOCATION, |caps| {
+            let place = caps[1].trim_end_matches(['.', '-', '\'']);
+            (!MONTHS.contains(&place.to_lowercase().as_str())).then(|| place.to_owned())
+        })
+        .into_iter()
+        .next();
+
+    let title = title(&masked.text);
+    let title_words = title.split_whitespace().count();
+    if title_words == 0 || title_words > MAX_TITLE_WORDS {
+        confident = false;
+    }
+    if msg.trim().lines().count() > 1
+        || msg.len() > MAX_SIMPLE_LEN
+        || RECURRING.is_match(&masked.text)
+    {
+        confident = false;
+    }
+
+    Some(RuleExtraction {
+        extraction: Extraction {
+            events: vec![ExtractedEvent {
+                title: if title.is_empty() {
+                    "Event".to_owned()
+                } else {
+                    title
+                },
+                date,
+                enddate: None,
+                starttime,
+                endtime,
+                timezone,
+                location,
+                description: None,
+                recurrence: None,
+            }],
+        },
+        confidence: if confident {
+            Confidence::High
+        } else {
+            Confidence::Low
+        },
+    })
+}
+
+/// A date on its own, like "tomorrow", "Friday", "9/21" or "2025-09-21", in the date grammar.
+pub fn date_phrase(phrase: &str) -> Option<String> {
+    let phrase = phrase.trim();
+    if let Ok(date) = NaiveDate::parse_from_str(phrase, "%Y-%m-%d") {
+        return Some(date.format("x%Y%m%d").to_string());
+    }
+    whole(&SLASH_DATE, phrase)
+        .and_then(|caps| slash_date(&caps))
+        .or_else(|| whole(&DAY_WORD, phrase).and_then(|caps| day_word(&caps)))
+        .or_else(|| whole(&WEEKDAY, phrase).and_then(|caps| weekday(&caps)))
+}
+
+/// A time on its own, like "4pm", "4:30 PM", "16:30" or "noon", as HHMM.
+pub fn time_phrase(phrase: &str) -> Option<String> {
+    let phrase = phrase.trim();
+    if let Ok(time) = NaiveTime::parse_from_str(phrase, "%H%M") {
+        return Some(fmt_time(time));
+    }
+    let (time, _) = whole(&SINGLE_TIME, phrase).and_then(|caps| single_time(&caps))?;
+    Some(fmt_time(time))
+}
+
+/// Times on their own, like "4-6pm", "4:30 PM" or "17:00 to 19:00", as HHMM. `None` inside for
+/// an empty phrase or "all day".
+pub fn time_range_phrase(phrase: &str) -> Option<Option<(String, String)>> {
+    let phrase = phrase.trim();
+    if phrase.is_empty() || phrase.eq_ignore_ascii_case("all day") {
+        return Some(None);
+    }
+    if let Some(((start, _), (end, _))) = whole(&TIME_RANGE, phrase).and_then(|c| time_range(&c)) {
+        return Some(Some((fmt_time(start), fmt_time(end))));
+    }
+    let time = time_phrase(phrase)?;
+    Some(Some((time.clone(), time)))
+}
+
+/// `re`'s match only if it covers all of `text`.
+fn whole<'t>(re: &Regex, text: &'t str) -> Option<Captures<'t>> {
+    re.captures(text)
+        .filter(|caps| caps.get(0).is_some_and(|m| m.len() == text.len()))
+}
+
+fn iso_date(caps: &Captures) -> Option<String> {
+    let date = NaiveDate::from_ymd_opt(
+        caps[1].parse().ok()?,
+        caps[2].parse().ok()?,
+        caps[3].parse().ok()?,
+    )?;
+    Some(date.format("x%Y%m%d").to_string())
+}
+
+fn slash_date(caps: &Captures) -> Option<String> {
+    let month: u32 = caps[1].parse().ok()?;
+    let day: u32 = caps[2].parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some(match caps.get(3).map(|y| y.as_str()) {
+        Some(year) if year.len() == 2 => format!("x20{year}{month:02}{day:02}"),
+        Some(year) => format!("x{year}{month:02}{day:02}"),
+        None => format!("x{month:02}{day:02}"),
+    })
+}
+
+fn day_word(caps: &Captures) -> Option<String> {
+    Some(match caps[1].to_lowercase().as_str() {
+        "today" | "tonight" => "+0".to_owned(),
+        _ => "+1".to_owned(),
+    })
+}
+
+fn weekday(caps: &Captures) -> Option<String> {
+    Some(format!("_{}", caps[1][..3].to_lowercase()))
+}
+
+fn single_time(caps: &Captures) -> Option<(NaiveTime, bool)> {
+    if caps.get(6).is_some() {
+        Some((NaiveTime::from_hms_opt(12, 0, 0)?, true))
+    } else if let Some(hour) = caps.get(4) {
+        clock_time(hour.as_str(), caps.get(5).map(|m| m.as_str()), None)
+    } else {
+        clock_time(&caps[1], caps.get(2).map(|m| m.as_str()), Some(&caps[3]))
+    }
+}
+
+fn fmt_time(time: NaiveTime) -> String {
+    time.format("%H%M").to_string()
+}
+
+/// A time of day, and whether it was unambiguous (had AM/PM or was clearly 24 hour time).
+/// Without either, the time is assumed to be between 9AM and 8PM.
+fn clock_time(
+    hour: &str,
+    minute: Option<&str>,
+    meridiem: Option<&str>,
+) -> Option<(NaiveTime, bool)> {
+    let hour: u32 = hour.parse().ok()?;
+    let minute: u32 = minute.map_or(Ok(0), str::parse).ok()?;
+    let (hour, sure) = match meridiem.map(|m| m.to_ascii_lowercase()) {
+        Some(m) if (1..=12).contains(&hour) => (hour % 12 + if m == "a" { 0 } else { 12 }, true),
+        Some(_) => return None,
+        None if hour == 0 || hour > 12 => (hour, true),
+        None if (9..=11).contains(&hour) => (hour, false),
+        None => (hour % 12 + 12, false),
+    };
+    Some((NaiveTime::from_hms_opt(hour, minute, 0)?, sure))
+}
+
+type ClockRange = ((NaiveTime, bool), (NaiveTime, bool));
+
+/// Reads "4-6pm", "11am to 1" or "10pm-2am". An end without AM/PM is the first matching time
+/// after the start, a start without AM/PM the last one before the end.
+fn time_range(caps: &Captures) -> Option<ClockRange> {
+    let text = |i| caps.get(i).map(|m| m.as_str());
+    let start_hour: u32 = caps[1].parse().ok()?;
+    let end_hour: u32 = caps[4].parse().ok()?;
+    if start_hour > 12 || end_hour > 12 {
+        // 24 hour time, e.g. "17:00-19:00"
+        return Some((
+            clock_time(&caps[1], text(2), None)?,
+            clock_time(&caps[4], text(5), None)?,
+        ));
+    }
+    let twelve_hour = |hour: u32, minute: Option<&str>| -> Option<[NaiveTime; 2]> {
+        let minute: u32 = minute.map_or(Ok(0), str::parse).ok()?;
+        Some([
+            NaiveTime::from_hms_opt(hour % 12, minute, 0)?,
+            NaiveTime::from_hms_opt(hour % 12 + 12, minute, 0)?,
+        ])
+    };
+
+    match (text(3), text(6)) {
+        (Some(start), Some(end)) => Some((
+            clock_time(&caps[1], text(2), Some(start))?,
+            clock_time(&caps[4], text(5), Some(end))?,
+        )),
+        (Some(start), None) => {
+            let start = clock_time(&caps[1], text(2), Some(start))?;
+            let options = twelve_hour(end_hour, text(5))?;
+            let end = options
+                .into_iter()
+                .find(|end| *end >= start.0)
+                .unwrap_or(options[0]);
+            Some((start, (end, true)))
+        }
+        (None, Some(end)) => {
+            let end = clock_time(&caps[4], text(5), Some(end))?;
+            let options = twelve_hour(start_hour, text(2))?;
+            let start = options
+                .into_iter()
+                .rev()
+                .find(|start| *start <= end.0)
+                .unwrap_or(options[1]);
+            Some(((start, true), end))
+        }
+        (None, None) => {
+            let start = clock_time(&caps[1], text(2), None)?;
+            let options = twelve_hour(end_hour, text(5))?;
+            let end = options
+                .into_iter()
+                .find(|end| *end >= start.0)
+                .unwrap_or(options[0]);
+            Some((start, (end, false)))
+        }
+    }
+}
+
+/// Whatever is left of the first line once dates, times and places are taken out, e.g.
+/// "ACM Club is meeting || from ||||" becomes "ACM Club".
+fn title(masked: &str) -> String {
+    const TRAILING: [&str; 16] = [
+        "on",
+        "at",
+        "from",
+        "this",
+        "next",
+        "the",
+        "is",
+        "are",
+        "will",
+        "be",
+        "held",
+        "happening",
+        "meets",
+        "starts",
+        "starting",
+        "in",
+    ];
+    let line = masked
+        .lines()
+        .find(|line| line.chars().any(char::is_alphanumeric))
+        .unwrap_or_default();
+
+    for segment in line.split(['|', ',', '.', '!', '?', ':', ';', '(', ')']) {
+        let mut words: Vec<_> = segment.split_whitespace().collect();
+        loop {
+            match words.as_slice() {
+                // "ACM is meeting" is about the ACM, not a meeting
+                [.., "is" | "are" | "be", "meeting"] => {
+                    words.truncate(words.len() - 2);
+                }
+                [.., last] if TRAILING.contains(&last.to_lowercase().as_str()) => {
+                    words.pop();
+                }
+                _ => break,
+            }
+        }
+        if words.iter().any(|w| w.chars().any(char::is_alphabetic)) {
+            return words.join(" ");
+        }
+    }
+    String::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(msg: &str) -> (ExtractedEvent, Confidence) {
+        let mut found = extract(msg).expect("message should have a date");
+        (found.extraction.events.remove(0), found.confidence)
+    }
+
+    #[test]
+    fn simple_meeting() {
+        let (event, confidence) = event("ACM meeting tomorrow 4-6pm in OCNL 241");
+        assert_eq!(confidence, Confidence::High);
+        assert_eq!(event.title, "ACM meeting");
+        assert_eq!(event.date, "+1");
+        assert_eq!(event.starttime.as_deref(), Some("1600"));
+        assert_eq!(event.endtime.as_deref(), Some("1800"));
+        assert_eq!(event.location.as_deref(), Some("OCNL 241"));
+    }
+
+    #[test]
+    fn readme_example() {
+        let (event, confidence) =
+            event("<@1334671194925961246> ACM Club is meeting today from 4-6pm in OCNL 241!");
+        assert_eq!(confidence, Confidence::High);
+        assert_eq!(event.title, "ACM Club");
+        assert_eq!(event.date, "+0");
+        assert_eq!(event.location.as_deref(), Some("OCNL 241"));
+    }
+
+    #[test]
+    fn weekdays_and_slash_dates() {
+        let (event, _) = event("Game night on Friday at 7:30pm @ BMU 210");
+        assert_eq!(event.title, "Game night");
+        assert_eq!(event.date, "_fri");
+        assert_eq!(event.starttime.as_deref(), Some("1930"));
+        assert_eq!(event.location.as_deref(), Some("BMU 210"));
+
+        assert_eq!(
+            event_date("Career fair 9/21 10am-2pm in Acker Gym"),
+            "x0921"
+        );
+        assert_eq!(event_date("Hackathon 3/7/2026 9am-5pm"), "x20260307");
+        assert_eq!(event_date("Hackathon 3/7/26 9am-5pm"), "x20260307");
+        // "sat" is only a weekday when it's capitalized
+        assert!(extract("we sat down for lunch").is_none());
+    }
+
+    #[test]
+    fn iso_dates() {
+        // the month and day aren't a time range
+        let (event, _) = event("Hackathon 2025-09-21 at 4pm");
+        assert_eq!(event.date, "x20250921");
+        assert_eq!(event.starttime.as_deref(), Some("1600"));
+        assert_eq!(event.endtime.as_deref(), Some("1600"));
+        assert_eq!(event_date("Hackathon 2025-9-21 9-5"), "x20250921");
+        assert!(extract("Hackathon 2025-13-21 at 4pm").is_none());
+    }
+
+    fn event_date(msg: &str) -> String {
+        event(msg).0.date
+    }
+
+    #[test]
+    fn time_ranges() {
+        let times = |msg: &str| {
+            let (event, confidence) = event(msg);
+            (
+                event.starttime.unwrap(),
+                event.endtime.unwrap(),
+                confidence == Confidence::High,
+            )
+        };
+        assert_eq!(
+            times("Lunch today 11-1pm"),
+            ("1100".into(), "1300".into(), true)
+        );
+        assert_eq!(
+            times("Lunch today 11am-1"),
+            ("1100".into(), "1300".into(), true)
+        );
+        assert_eq!(
+            times("LAN party today 10pm-2am"),
+            ("2200".into(), "0200".into(), true)
+        );
+        assert_eq!(
+            times("Study hall today 17:00-19:30"),
+            ("1700".into(), "1930".into(), true)
+        );
+        // no AM/PM at all is a guess
+        assert_eq!(
+            times("Movie night today 8-10"),
+            ("2000".into(), "2200".into(), false)
+        );
+        assert_eq!(
+            times("Office hours today at noon"),
+            ("1200".into(), "1200".into(), true)
+        );
+    }
+
+    #[test]
+    fn time_zone() {
+        let (event, confidence) = event("Online workshop tomorrow 5-6pm PST");
+        assert_eq!(confidence, Confidence::High);
+        assert_eq!(event.timezone.as_deref(), Some("PST"));
+        assert_eq!(event.title, "Online workshop");
+    }
+
+    #[test]
+    fn low_confidence() {
+        let low = |msg| event(msg).1 == Confidence::Low;
+        // no time
+        assert!(low("Career fair tomorrow in Acker Gym"));
+        // two dates
+        assert!(low("Workshop Monday and Wednesday 4-5pm"));
+        // recurring
+        assert!(low("Cybersecurity club every Monday 5-6pm in OCNL 239"));
+        // more than one line
+        assert!(low(
+            "Game jam tomorrow 5-9pm\nBring your laptop and a charger!"
+        ));
+        // no title
+        assert!(low("tomorrow 4-6pm"));
+    }
+
+    #[test]
+    fn voice_channel_location() {
+        let (event, confidence) = event("Game night tomorrow 7-9pm in lounge voice channel");
+        assert_eq!(confidence, Confidence::High);
+        assert_eq!(event.location.as_deref(), Some("lounge voice channel"));
+        assert_eq!(event.title, "Game night");
+    }
+
+    #[test]
+    fn phrases() {
+        assert_eq!(date_phrase("tomorrow").as_deref(), Some("+1"));
+        assert_eq!(date_phrase(" Friday ").as_deref(), Some("_fri"));
+        assert_eq!(date_phrase("9/21").as_deref(), Some("x0921"));
+        assert_eq!(date_phrase("2025-09-21").as_deref(), Some("x20250921"));
+        assert_eq!(date_phrase("sometime in May"), None);
+
+        assert_eq!(time_phrase("4pm").as_deref(), Some("1600"));
+        assert_eq!(time_phrase("4:30 PM").as_deref(), Some("1630"));
+        assert_eq!(time_phrase("16:30").as_deref(), Some("1630"));
+        assert_eq!(time_phrase("0930").as_deref(), Some("0930"));
+        assert_eq!(time_phrase("noon").as_deref(), Some("1200"));
+        assert_eq!(time_phrase("4pm-6pm"), None);
+
+        let range = |start: &str, end: &str| Some(Some((start.to_owned(), end.to_owned())));
+        assert_eq!(
+            time_range_phrase("4:00 PM - 6:00 PM"),
+            range("1600", "1800")
+        );
+        assert_eq!(time_range_phrase("4-6pm"), range("1600", "1800"));
+        assert_eq!(time_range_phrase("4pm"), range("1600", "1600"));
+        assert_eq!(time_range_phrase(" All day "), Some(None));
+        assert_eq!(time_range_phrase("later"), None);
+    }
+
+    #[test]
+    fn no_date() {
+        assert!(extract("69420").is_none());
+        assert!(extract("ACM meets after the career fair, 4-6pm in OCNL 241").is_none());
+    }
+}
@@ -0,0 +1,441 @@
+//! Discord's own scheduled events, which members can mark themselves "Interested" in.
+//!
+//! Guilds get one for every event they confirm after turning them on with
+//! `/settings discord_events:True`, or for a single event with `/event discord_event:True`.
+//! Edits and deletions of the announcement carry over to them like they do to the .ics files.
+//!
+//! It also goes the other way: with `/settings import_events:True`, scheduled events made in
+//! Discord are posted as calendar events, see `Handler::import`.
+
+use chrono::{DateTime, Duration, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use icalendar::{CalendarDateTime, Component, DatePerhapsTime, Event, EventLike};
+use serenity::all::{
+    ChannelId, ChannelType, Context, CreateScheduledEvent, EditScheduledEvent, GuildId,
+    ScheduledEvent, ScheduledEventId, ScheduledEventStatus, ScheduledEventType,
+};
+
+use crate::{mentions::channel_name, store::Store, timezone::guild_timezone};
+
+/// Discord's limits on the name and description of a scheduled event.
+const MAX_NAME: usize = 100;
+const MAX_DESCRIPTION: usize = 1000;
+
+/// An event as Discord wants it.
+#[derive(Debug, PartialEq)]
+struct Schedule {
+    name: String,
+    description: Option<String>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    place: Place,
+}
+
+#[derive(Debug, PartialEq)]
+enum Place {
+    /// A voice channel or stage of the guild
+    Channel(ChannelId, ScheduledEventType),
+    External(String),
+}
+
+/// A guild channel, as far as finding where an event happens goes.
+struct GuildChannel {
+    id: ChannelId,
+    name: String,
+    kind: ChannelType,
+}
+
+/// What Discord should show for `event`, or `None` if it already started, which Discord doesn't
+/// allow. Its location is a voice channel or stage when it names one of `channels`.
+fn schedule(
+    event: &Event,
+    tz: Option<Tz>,
+    channels: &[GuildChannel],
+    now: DateTime<Utc>,
+) -> Option<Schedule> {
+    let start_time = event.get_start()?;
+    // Discord needs an end, so events without one last an hour, or the day if they're all-day
+    let length = match start_time {
+        DatePerhapsTime::Date(_) => Duration::days(1),
+        DatePerhapsTime::DateTime(_) => Duration::hours(1),
+    };
+    let start = utc(start_time, tz)?;
+    if start <= now {
+        return None;
+    }
+    let end = event
+        .get_end()
+        .and_then(|end| utc(end, tz))
+        .filter(|end| *end > start)
+        .unwrap_or(start + length);
+
+    let location = event.get_location().map(str::trim).unwrap_or_default();
+    let channel = channels.iter().find(|c| {
+        matches!(c.kind, ChannelType::Voice | ChannelType::Stage)
+            && (location.eq_ignore_ascii_case(&c.name)
+                || location.eq_ignore_ascii_case(&channel_name(&c.name, c.kind)))
+    });
+    let place = match channel {
+        Some(c) if c.kind == ChannelType::Stage => {
+            Place::Channel(c.id, ScheduledEventType::StageInstance)
+        }
+        Some(c) => Place::Channel(c.id, ScheduledEventType::Voice),
+        None if location.is_empty() => Place::External("TBD".to_owned()),
+        None => Place::External(truncate(location, MAX_NAME)),
+    };
+
+    Some(Schedule {
+        name: truncate(event.get_summary().unwrap_or("Event"), MAX_NAME),
+        description: event
+            .get_description()
+            .filter(|d| !d.is_empty())
+            .map(|d| truncate(d, MAX_DESCRIPTION)),
+        start,
+        end,
+        place,
+    })
+}
+
+/// When an event boundary is, with floating times and all-day events in the guild's time zone.
+fn utc(time: DatePerhapsTime, tz: Option<Tz>) -> Option<DateTime<Utc>> {
+    let local = |dt: NaiveDateTime, tz: Option<Tz>| match tz {
+        Some(tz) => tz.from_local_datetime(&dt).earliest().map(|dt| dt.to_utc()),
+        None => Some(dt.and_utc()),
+    };
+    match time {
+        DatePerhapsTime::Date(date) => local(date.and_hms_opt(0, 0, 0)?, tz),
+        DatePerhapsTime::DateTime(CalendarDateTime::Floating(dt)) => local(dt, tz),
+        DatePerhapsTime::DateTime(CalendarDateTime::Utc(dt)) => Some(dt),
+        DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone { date_time, tzid }) => {
+            local(date_time, tzid.parse().ok().or(tz))
+        }
+    }
+}
+
+/// `s`, cut to `max` characters with an ellipsis if it's longer.
+fn truncate(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        return s.to_owned();
+    }
+    let cut: String = s.chars().take(max - 1).collect();
+    format!("{}…", cut.trim_end())
+}
+
+fn create(schedule: Schedule) -> CreateScheduledEvent<'static> {
+    let (kind, channel, location) = match schedule.place {
+        Place::Channel(id, kind) => (kind, Some(id), None),
+        Place::External(location) => (ScheduledEventType::External, None, Some(location)),
+    };
+    let mut builder =
+        CreateScheduledEvent::new(kind, schedule.name, schedule.start).end_time(schedule.end);
+    if let Some(id) = channel {
+        builder = builder.channel_id(id);
+    }
+    if let Some(location) = location {
+        builder = builder.location(location);
+    }
+    if let Some(description) = schedule.description {
+        builder = builder.description(description);
+    }
+    builder
+}
+
+fn edit(schedule: Schedule) -> EditScheduledEvent<'static> {
+    let builder = EditScheduledEvent::new()
+        .name(schedule.name)
+        .description(schedule.description.unwrap_or_default())
+        .start_time(schedule.start)
+        .end_time(schedule.end);
+    match schedule.place {
+        Place::Channel(id, kind) => builder.kind(kind).channel_id(id),
+        Place::External(location) => builder
+            .kind(ScheduledEventType::External)
+            .location(location),
+    }
+}
+
+/// The guild's channels, from the cache.
+fn guild_channels(ctx: &Context, guild_id: GuildId) -> Vec<GuildChannel> {
+    let Some(guild) = ctx.cache.guild(guild_id) else {
+        return Vec::new();
+    };
+    guild
+        .channels
+        .values()
+        .map(|c| GuildChannel {
+            id: c.id,
+            name: c.name.clone(),
+            kind: c.kind,
+        })
+        .collect()
+}
+
+/// Makes the guild's scheduled events match `events`. `previous` are the ones already made for
+/// the same announcement, by UID: those are updated, and deleted if their event is gone. Events
+/// that already started are left alone.
+pub async fn sync(
+    ctx: &Context,
+    store: &Store,
+    guild_id: GuildId,
+    events: &[Event],
+    previous: &[(String, ScheduledEventId)],
+) -> serenity::Result<()> {
+    let tz = guild_timezone(Some(guild_id));
+    let channels = guild_channels(ctx, guild_id);
+    let now = Utc::now();
+    for event in events {
+        let Some(uid) = event.get_uid() else {
+            continue;
+        };
+        let Some(schedule) = schedule(event, tz, &channels, now) else {
+            continue;
+        };
+        match previous.iter().find(|(p, _)| p == uid) {
+            Some((_, id)) => {
+                guild_id
+                    .edit_scheduled_event(ctx, *id, edit(schedule))
+                    .await?;
+            }
+            None => {
+                let created = guild_id
+                    .create_scheduled_event(ctx, create(schedule))
+                    .await?;
+                if let Err(why) = store.set_scheduled_event(uid, created.id) {
+                    println!("Error recording scheduled event: {why}");
+                }
+            }
+        }
+    }
+    for (uid, id) in previous {
+        if !events.iter().any(|e| e.get_uid() == Some(uid)) {
+            guild_id.delete_scheduled_event(ctx, *id).await?;
+        }
+    }
+    Ok(())
+}
+
+/// The calendar event for a scheduled event made in Discord, in the guild's time zone.
+pub fn imported_event(ctx: &Context, scheduled: &ScheduledEvent) -> Event {
+    let location = match scheduled.kind {
+        ScheduledEventType::External => {
+            scheduled.metadata.as_ref().and_then(|m| m.location.clone())
+        }
+        _ => scheduled.channel_id.and_then(|id| {
+            let guild = ctx.cache.guild(scheduled.guild_id)?;
+            let channel = guild.channels.get(&id)?;
+            Some(channel_name(&channel.name, channel.kind))
+        }),
+    };
+    to_event(
+        scheduled,
+        location,
+        guild_timezone(Some(scheduled.guild_id)),
+    )
+}
+
+fn to_event(scheduled: &ScheduledEvent, location: Option<String>, tz: Option<Tz>) -> Event {
+    let time = |at: DateTime<Utc>| match tz {
+        Some(tz) => CalendarDateTime::from_date_time(at.with_timezone(&tz)),
+        None => CalendarDateTime::Utc(at),
+    };
+    let mut event = Event::new();
+    event
+        .uid(&format!(
+            "{}-event-{}@calbot",
+            scheduled.guild_id, scheduled.id
+        ))
+        .summary(&scheduled.name)
+        .starts(time(*scheduled.start_time))
+        // voice and stage events don't need an end, so those last an hour like in `schedule`
+        .ends(time(
+            scheduled
+                .end_time
+                .map_or(*scheduled.start_time + Duration::hours(1), |end| *end),
+        ));
+    if let Some(location) = location.filter(|l| !l.is_empty()) {
+        event.location(&location);
+    }
+    if let Some(description) = scheduled.description.as_deref().filter(|d| !d.is_empty()) {
+        event.description(description);
+    }
+    event.done()
+}
+
+/// Cancels scheduled events made for a deleted announcement.
+pub async fn cancel(
+    ctx: &Context,
+    guild_id: GuildId,
+    previous: &[(String, ScheduledEventId)],
+) -> serenity::Result<()> {
+    for (_, id) in previous {
+        let edit = EditScheduledEvent::new().status(ScheduledEventStatus::Canceled);
+        guild_id.edit_scheduled_event(ctx, *id, edit).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use icalendar::Calendar;
+
+    use super::*;
+    use crate::review::draft_content;
+
+    #[test]
+    fn schedules() {
+        let la = chrono_tz::America::Los_Angeles;
+        let now = Utc.with_ymd_and_hms(2025, 9, 1, 0, 0, 0).unwrap();
+        let start = NaiveDate::from_ymd_opt(2025, 9, 21)
+            .unwrap()
+            .and_hms_opt(16, 0, 0)
+            .unwrap();
+        let channels = [
+            GuildChannel {
+                id: ChannelId::new(1),
+                name: "Lounge".to_owned(),
+                kind: ChannelType::Voice,
+            },
+            GuildChannel {
+                id: ChannelId::new(2),
+                name: "events".to_owned(),
+                kind: ChannelType::Text,
+            },
+        ];
+        let event = Event::new()
+            .summary("ACM General Meeting")
+            .starts(CalendarDateTime::Floating(start))
+            .location("OCNL 241")
+            .done();
+        assert_eq!(
+            schedule(&event, Some(la), &channels, now),
+            Some(Schedule {
+                name: "ACM General Meeting".to_owned(),
+                description: None,
+                start: Utc.with_ymd_and_hms(2025, 9, 21, 23, 0, 0).unwrap(),
+                end: Utc.with_ymd_and_hms(2025, 9, 22, 0, 0, 0).unwrap(),
+                place: Place::External("OCNL 241".to_owned()),
+            })
+        );
+
+        let in_voice = Event::new()
+            .summary("Game night")
+            .starts(CalendarDateTime::Floating(start))
+            .ends(CalendarDateTime::Floating(start + Duration::hours(3)))
+            .location("lounge voice channel")
+            .description("Bring snacks")
+            .done();
+        let scheduled = schedule(&in_voice, Some(la), &channels, now).unwrap();
+        assert_eq!(
+            scheduled.place,
+            Place::Channel(ChannelId::new(1), ScheduledEventType::Voice)
+        );
+        assert_eq!(scheduled.end - scheduled.start, Duration::hours(3));
+        assert_eq!(scheduled.description.as_deref(), Some("Bring snacks"));
+
+        // text channels aren't somewhere events happen
+        let in_text = Event::new()
+            .summary("AMA")
+            .starts(CalendarDateTime::Floating(start))
+            .location("#events")
+            .done();
+        let scheduled = schedule(&in_text, Some(la), &channels, now).unwrap();
+        assert_eq!(scheduled.place, Place::External("#events".to_owned()));
+
+        // all-day events last the day, and need somewhere to be
+        let all_day = Event::new()
+            .summary("Hackathon")
+            .starts(start.date())
+            .done();
+        let scheduled = schedule(&all_day, None, &channels, now).unwrap();
+        assert_eq!(
+            scheduled.start,
+            Utc.with_ymd_and_hms(2025, 9, 21, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            scheduled.end,
+            Utc.with_ymd_and_hms(2025, 9, 22, 0, 0, 0).unwrap()
+        );
+        assert_eq!(scheduled.place, Place::External("TBD".to_owned()));
+
+        let later = Utc.with_ymd_and_hms(2025, 10, 1, 0, 0, 0).unwrap();
+        assert_eq!(schedule(&event, Some(la), &channels, later), None);
+    }
+
+    #[test]
+    fn imports() {
+        let scheduled: ScheduledEvent = serde_json::from_value(serde_json::json!({
+            "id": "41",
+            "guild_id": "1",
+            "channel_id": null,
+            "creator_id": "3",
+            "name": "ACM General Meeting",
+            "description": "Pizza!",
+            "scheduled_start_time": "2025-09-21T23:00:00+00:00",
+            "scheduled_end_time": "2025-09-22T01:00:00+00:00",
+            "privacy_level": 2,
+            "status": 1,
+            "entity_type": 3,
+            "entity_id": null,
+            "entity_metadata": { "location": "OCNL 241" },
+        }))
+        .unwrap();
+        let la = chrono_tz::America::Los_Angeles;
+        let event = to_event(&scheduled, Some("OCNL 241".to_owned()), Some(la));
+        assert_eq!(event.get_uid(), Some("1-event-41@calbot"));
+        assert_eq!(event.get_summary(), Some("ACM General Meeting"));
+        assert_eq!(event.get_description(), Some("Pizza!"));
+        assert_eq!(event.get_location(), Some("OCNL 241"));
+        let start = NaiveDate::from_ymd_opt(2025, 9, 21)
+            .unwrap()
+            .and_hms_opt(16, 0, 0)
+            .unwrap();
+        assert_eq!(
+            event.get_start(),
+            Some(CalendarDateTime::from_date_time(start.and_local_timezone(la).unwrap()).into())
+        );
+
+        let utc = to_event(&scheduled, None, None);
+        assert_eq!(
+            utc.get_end(),
+            Some(CalendarDateTime::Utc(Utc.with_ymd_and_hms(2025, 9, 22, 1, 0, 0).unwrap()).into())
+        );
+        assert_eq!(utc.get_location(), None);
+    }
+
+    #[test]
+    fn imports_without_end() {
+        let scheduled: ScheduledEvent = serde_json::from_value(serde_json::json!({
+            "id": "42",
+            "guild_id": "1",
+            "channel_id": "5",
+            "creator_id": "3",
+            "name": "Game night",
+            "scheduled_start_time": "2025-09-21T17:00:00+00:00",
+            "privacy_level": 2,
+            "status": 1,
+            "entity_type": 2,
+            "entity_id": null,
+            "entity_metadata": null,
+        }))
+        .unwrap();
+        let event = to_event(&scheduled, Some("Lounge voice channel".to_owned()), None);
+        assert_eq!(
+            event.get_end(),
+            Some(
+                CalendarDateTime::Utc(Utc.with_ymd_and_hms(2025, 9, 21, 18, 0, 0).unwrap()).into()
+            )
+        );
+        let content = draft_content(&Calendar::new().push(event).done());
+        assert!(content.contains("**End Time**:  6:00 PM UTC"), "{content}");
+    }
+
+    #[test]
+    fn truncates() {
+        assert_eq!(truncate("Meeting", 100), "Meeting");
+        let long = "a".repeat(120);
+        let truncated = truncate(&long, 100);
+        assert_eq!(truncated.chars().count(), 100);
+        assert!(truncated.ends_with("a…"));
+    }
+}
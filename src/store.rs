@@ -0,0 +1,1062 @@
+//! Every event the bot has published, in an embedded SQLite database at `CALBOT_DB`
+//! (`calbot.db` by default).
+//!
+//! The schema is created and upgraded by [`MIGRATIONS`], with SQLite's `user_version` recording
+//! how many have been applied.
+
+use std::{env, str::FromStr, sync::Mutex};
+
+use chrono::Utc;
+use icalendar::{
+    Calendar, CalendarComponent, CalendarDateTime, Component, DatePerhapsTime, Event, EventLike,
+    EventStatus,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serenity::all::{ChannelId, GuildId, MessageId, ScheduledEventId, UserId};
+use uuid::Uuid;
+
+use crate::{reactions, utils::CalendarUrls};
+
+/// Applied in order, each exactly once. Only ever append to this.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE events (
+        id INTEGER PRIMARY KEY,
+        uid TEXT NOT NULL UNIQUE,
+        guild_id INTEGER,
+        channel_id INTEGER NOT NULL,
+        -- the announcement, NULL for /event
+        source_message_id INTEGER,
+        -- the bot's reply showing the event
+        reply_message_id INTEGER NOT NULL,
+        author_id INTEGER NOT NULL,
+        title TEXT NOT NULL,
+        -- ISO 8601 dates or local date-times, in `timezone` when there is one
+        starts_at TEXT NOT NULL,
+        ends_at TEXT,
+        timezone TEXT,
+        location TEXT,
+        description TEXT,
+        rrule TEXT,
+        -- the whole VEVENT, to rebuild the event from
+        ics TEXT NOT NULL,
+        attachment_url TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );
+    CREATE INDEX events_source ON events (source_message_id);
+    CREATE INDEX events_guild ON events (guild_id);
+",
+    "
+    CREATE TABLE feeds (
+        token TEXT PRIMARY KEY,
+        guild_id INTEGER NOT NULL,
+        -- NULL for the feed of the whole guild
+        channel_id INTEGER,
+        name TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );
+    CREATE UNIQUE INDEX feeds_scope ON feeds (guild_id, IFNULL(channel_id, 0));
+",
+    "
+    CREATE TABLE ics_files (
+        id TEXT PRIMARY KEY,
+        -- the UIDs of the events in the file, comma separated
+        key TEXT NOT NULL UNIQUE,
+        ics TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    );
+",
+    "
+    -- the SEQUENCE of the event, bumped every time it's converted again
+    ALTER TABLE events ADD COLUMN sequence INTEGER NOT NULL DEFAULT 0;
+",
+    "
+    -- set once the announcement is deleted, see `Handler::cancel`
+    ALTER TABLE events ADD COLUMN cancelled_at TEXT;
+",
+    "
+    -- the Discord scheduled event made for the event, see `scheduled`
+    ALTER TABLE events ADD COLUMN scheduled_event_id INTEGER;
+    CREATE TABLE guild_settings (
+        guild_id INTEGER PRIMARY KEY,
+        -- whether confirmed events also become Discord scheduled events
+        discord_events INTEGER NOT NULL DEFAULT 0
+    );
+",
+    "
+    -- where Discord scheduled events are posted as they're imported, NULL to not import them
+    ALTER TABLE guild_settings ADD COLUMN import_channel_id INTEGER;
+",
+    "
+    -- channels whose messages are converted without mentioning the bot, see `watch`
+    CREATE TABLE watched_channels (
+        channel_id INTEGER PRIMARY KEY,
+        guild_id INTEGER NOT NULL,
+        created_at TEXT NOT NULL
+    );
+    CREATE INDEX watched_channels_guild ON watched_channels (guild_id);
+",
+    "
+    -- the emoji that converts a message when reacted with, NULL for the default
+    ALTER TABLE guild_settings ADD COLUMN reaction_emoji TEXT;
+",
+    "
+    -- set for events only whoever converted them saw, which are left out of feeds
+    ALTER TABLE events ADD COLUMN private INTEGER NOT NULL DEFAULT 0;
+",
+];
+
+/// Where an event came from and who asked for it.
+#[derive(Debug, Clone, Copy)]
+pub struct Origin {
+    pub guild_id: Option<GuildId>,
+    pub channel_id: ChannelId,
+    /// The announcement, if the events were converted from one in public. Only then do edits
+    /// and deletions of it carry over to them.
+    pub source: Option<MessageId>,
+    pub author: UserId,
+    /// Whether the events were only shown to whoever converted them, so they stay out of feeds.
+    pub private: bool,
+}
+
+/// A subscribable calendar of a guild's events, or of one of its channels', see `feed`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Feed {
+    pub guild_id: GuildId,
+    pub channel_id: Option<ChannelId>,
+    pub name: String,
+}
+
+impl Origin {
+    /// `<guild>-<message>-<i>@calbot` for the `i`th event of an announcement, or
+    /// `<guild>-<message>-<sequence>-<i>@calbot` for one first found when it was converted again,
+    /// so a UID is never given to two different events. Events without an announcement keep the
+    /// random UID they were made with.
+    fn uid(&self, sequence: u32, i: usize) -> Option<String> {
+        let guild = self.guild_id.map_or(0, |id| id.get());
+        let source = self.source?;
+        Some(match sequence {
+            0 => format!("{guild}-{source}-{i}@calbot"),
+            _ => format!("{guild}-{source}-{sequence}-{i}@calbot"),
+        })
+    }
+}
+
+/// Which of `previous`, the events an announcement was last converted to, each of `events` is.
+///
+/// Events are matched by title and start, then by either one, so an event keeps its UID when it's
+/// moved or renamed, whatever order the announcement lists them in now.
+fn match_previous(previous: &[Event], events: &[&mut Event]) -> Vec<Option<usize>> {
+    let title = |e: &Event| e.get_summary().map(str::to_lowercase);
+    let same = |pass, a: &Event, b: &Event| match pass {
+        0 => title(a) == title(b) && a.get_start() == b.get_start(),
+        1 => title(a) == title(b),
+        _ => a.get_start() == b.get_start(),
+    };
+    let mut matched = vec![None; events.len()];
+    let mut taken = vec![false; previous.len()];
+    for pass in 0..3 {
+        for (event, matched) in events.iter().zip(&mut matched) {
+            if matched.is_some() {
+                continue;
+            }
+            let found = (0..previous.len()).find(|&j| !taken[j] && same(pass, &previous[j], event));
+            if let Some(j) = found {
+                taken[j] = true;
+                *matched = Some(j);
+            }
+        }
+    }
+    matched
+}
+
+/// An announcement's events that were already recorded.
+#[derive(Debug, Clone, Copy)]
+pub struct Converted {
+    pub origin: Origin,
+    /// The bot's reply showing them
+    pub reply: MessageId,
+    pub sequence: u32,
+}
+
+/// How a guild has set the bot up with `/settings`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GuildSettings {
+    pub discord_events: bool,
+    /// Where the guild's own Discord scheduled events are posted as calendar events, see
+    /// `Handler::import`
+    pub import_channel: Option<ChannelId>,
+    /// `None` for `reactions::DEFAULT_EMOJI`
+    pub reaction_emoji: Option<String>,
+}
+
+impl GuildSettings {
+    pub fn reaction_emoji(&self) -> &str {
+        self.reaction_emoji
+            .as_deref()
+            .unwrap_or(reactions::DEFAULT_EMOJI)
+    }
+}
+
+pub struct Store {
+    conn: Mutex<Connection>,
+}
+
+impl Store {
+    pub fn from_env() -> Self {
+        let path = env::var("CALBOT_DB").unwrap_or_else(|_| "calbot.db".to_owned());
+        let conn = Connection::open(&path).expect("Failed to open CALBOT_DB");
+        Self::new(conn).expect("Failed to migrate CALBOT_DB")
+    }
+
+    fn new(mut conn: Connection) -> rusqlite::Result<Self> {
+        migrate(&mut conn)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Gives the events of `calendar` UIDs derived from the announcement they came from, so
+    /// converting it again updates them instead of adding duplicates, and a SEQUENCE after the
+    /// last time it was converted. Events it was converted to before keep their UIDs, see
+    /// [`match_previous`].
+    pub fn identify(&self, calendar: &mut Calendar, origin: &Origin) -> rusqlite::Result<()> {
+        let (sequence, previous) = match origin.source {
+            Some(source) => (
+                self.converted(source)?.map_or(0, |c| c.sequence + 1),
+                self.source_events(source)?,
+            ),
+            None => (0, Vec::new()),
+        };
+        let now = Utc::now();
+        let mut events: Vec<_> = calendar
+            .components
+            .iter_mut()
+            .filter_map(|c| match c {
+                CalendarComponent::Event(event) => Some(event),
+                _ => None,
+            })
+            .collect();
+        let matched = match_previous(&previous, &events);
+        for (i, (event, matched)) in events.iter_mut().zip(matched).enumerate() {
+            let uid = match matched {
+                Some(j) => previous[j].get_uid().map(str::to_owned),
+                None => origin.uid(sequence, i),
+            };
+            if let Some(uid) = uid {
+                event.uid(&uid);
+            }
+            event.sequence(sequence).timestamp(now);
+        }
+        Ok(())
+    }
+
+    /// How an announcement was last converted, if it was.
+    pub fn converted(&self, source: MessageId) -> rusqlite::Result<Option<Converted>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT guild_id, channel_id, author_id, reply_message_id, sequence FROM events
+            WHERE source_message_id = ?1 ORDER BY sequence DESC, id LIMIT 1",
+            [source.get()],
+            |row| {
+                Ok(Converted {
+                    origin: Origin {
+                        guild_id: row.get::<_, Option<u64>>(0)?.map(GuildId::new),
+                        channel_id: ChannelId::new(row.get(1)?),
+                        source: Some(source),
+                        author: UserId::new(row.get(2)?),
+                        private: false,
+                    },
+                    reply: MessageId::new(row.get(3)?),
+                    sequence: row.get(4)?,
+                })
+            },
+        )
+        .optional()
+    }
+
+    /// The recorded events of an announcement, in order.
+    pub fn source_events(&self, source: MessageId) -> rusqlite::Result<Vec<Event>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare("SELECT ics FROM events WHERE source_message_id = ?1 ORDER BY id")?;
+        let rows = stmt.query_map([source.get()], |row| row.get::<_, String>(0))?;
+        Ok(rows.filter_map(|ics| parse_event(&ics.ok()?)).collect())
+    }
+
+    /// Records the events shown in `reply`, with the .ics each one can be downloaded from.
+    ///
+    /// Events already recorded with the same UID are updated, and events from the same
+    /// announcement that it no longer has are forgotten.
+    pub fn record(
+        &self,
+        origin: &Origin,
+        reply: MessageId,
+        events: &[Event],
+        urls: &CalendarUrls,
+    ) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let created_at = Utc::now().to_rfc3339();
+        for (i, event) in events.iter().enumerate() {
+            let Some((starts_at, timezone)) = event.get_start().map(iso_date_time) else {
+                continue;
+            };
+            let url = urls.events.get(i).unwrap_or(&urls.all);
+            tx.execute(
+                "INSERT INTO events (uid, guild_id, channel_id, source_message_id,
+                    reply_message_id, author_id, title, starts_at, ends_at, timezone, location,
+                    description, rrule, ics, attachment_url, created_at, sequence, cancelled_at,
+                    private)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16,
+                    ?17, ?18, ?19)
+                ON CONFLICT (uid) DO UPDATE SET reply_message_id = excluded.reply_message_id,
+                    title = excluded.title, starts_at = excluded.starts_at,
+                    ends_at = excluded.ends_at, timezone = excluded.timezone,
+                    location = excluded.location, description = excluded.description,
+                    rrule = excluded.rrule, ics = excluded.ics,
+                    attachment_url = excluded.attachment_url, sequence = excluded.sequence,
+                    cancelled_at = excluded.cancelled_at",
+                params![
+                    event.get_uid(),
+                    origin.guild_id.map(|id| id.get()),
+                    origin.channel_id.get(),
+                    origin.source.map(|id| id.get()),
+                    reply.get(),
+                    origin.author.get(),
+                    event.get_summary().unwrap_or_default(),
+                    starts_at,
+                    event.get_end().map(|end| iso_date_time(end).0),
+                    timezone,
+                    event.get_location(),
+                    event.get_description(),
+                    event.property_value("RRULE"),
+                    event.to_string(),
+                    url,
+                    created_at,
+                    event.get_sequence().unwrap_or_default(),
+                    (event.get_status() == Some(EventStatus::Cancelled)).then_some(&created_at),
+                    origin.private,
+                ],
+            )?;
+        }
+        if let Some(source) = origin.source {
+            let uids: Vec<_> = events.iter().filter_map(|e| e.get_uid()).collect();
+            tx.execute(
+                "DELETE FROM events WHERE source_message_id = ?1
+                AND uid NOT IN (SELECT value FROM json_each(?2))",
+                params![source.get(), serde_json::to_string(&uids).unwrap()],
+            )?;
+        }
+        tx.commit()
+    }
+
+    /// The Discord scheduled events made for an announcement's events, by UID.
+    pub fn scheduled_events(
+        &self,
+        source: MessageId,
+    ) -> rusqlite::Result<Vec<(String, ScheduledEventId)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT uid, scheduled_event_id FROM events
+            WHERE source_message_id = ?1 AND scheduled_event_id IS NOT NULL ORDER BY id",
+        )?;
+        let rows = stmt.query_map([source.get()], |row| {
+            Ok((row.get(0)?, ScheduledEventId::new(row.get(1)?)))
+        })?;
+        rows.collect()
+    }
+
+    /// How a Discord scheduled event was last imported, if it was, and the event it became.
+    pub fn imported(&self, id: ScheduledEventId) -> rusqlite::Result<Option<(Converted, Event)>> {
+        let conn = self.conn.lock().unwrap();
+        let row = conn
+            .query_row(
+                "SELECT guild_id, channel_id, author_id, reply_message_id, sequence, ics
+                FROM events WHERE scheduled_event_id = ?1 AND source_message_id IS NULL",
+                [id.get()],
+                |row| {
+                    let converted = Converted {
+                        origin: Origin {
+                            guild_id: row.get::<_, Option<u64>>(0)?.map(GuildId::new),
+                            channel_id: ChannelId::new(row.get(1)?),
+                            source: None,
+                            author: UserId::new(row.get(2)?),
+                            private: false,
+                        },
+                        reply: MessageId::new(row.get(3)?),
+                        sequence: row.get(4)?,
+                    };
+                    Ok((converted, row.get::<_, String>(5)?))
+                },
+            )
+            .optional()?;
+        Ok(row.and_then(|(converted, ics)| Some((converted, parse_event(&ics)?))))
+    }
+
+    pub fn set_scheduled_event(&self, uid: &str, id: ScheduledEventId) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE events SET scheduled_event_id = ?2 WHERE uid = ?1",
+            params![uid, id.get()],
+        )?;
+        Ok(())
+    }
+
+    pub fn settings(&self, guild_id: GuildId) -> rusqlite::Result<GuildSettings> {
+        let conn = self.conn.lock().unwrap();
+        let settings = conn
+            .query_row(
+                "SELECT discord_events, import_channel_id, reaction_emoji FROM guild_settings
+                WHERE guild_id = ?1",
+                [guild_id.get()],
+                |row| {
+                    Ok(GuildSettings {
+                        discord_events: row.get(0)?,
+                        import_channel: row.get::<_, Option<u64>>(1)?.map(ChannelId::new),
+                        reaction_emoji: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(settings.unwrap_or_default())
+    }
+
+    pub fn save_settings(
+        &self,
+        guild_id: GuildId,
+        settings: &GuildSettings,
+    ) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO guild_settings (guild_id, discord_events, import_channel_id,
+                reaction_emoji)
+            VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (guild_id) DO UPDATE SET discord_events = excluded.discord_events,
+                import_channel_id = excluded.import_channel_id,
+                reaction_emoji = excluded.reaction_emoji",
+            params![
+                guild_id.get(),
+                settings.discord_events,
+                settings.import_channel.map(|id| id.get()),
+                settings.reaction_emoji,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Starts or stops watching a channel, returning whether that changed anything.
+    pub fn watch(
+        &self,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        watched: bool,
+    ) -> rusqlite::Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let changed = match watched {
+            true => conn.execute(
+                "INSERT INTO watched_channels (channel_id, guild_id, created_at)
+                VALUES (?1, ?2, ?3) ON CONFLICT DO NOTHING",
+                params![channel_id.get(), guild_id.get(), Utc::now().to_rfc3339()],
+            )?,
+            false => conn.execute(
+                "DELETE FROM watched_channels WHERE channel_id = ?1",
+                [channel_id.get()],
+            )?,
+        };
+        Ok(changed > 0)
+    }
+
+    pub fn is_watched(&self, channel_id: ChannelId) -> rusqlite::Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM watched_channels WHERE channel_id = ?1)",
+            [channel_id.get()],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn watched_channels(&self, guild_id: GuildId) -> rusqlite::Result<Vec<ChannelId>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT channel_id FROM watched_channels WHERE guild_id = ?1 ORDER BY created_at",
+        )?;
+        let rows = stmt.query_map([guild_id.get()], |row| Ok(ChannelId::new(row.get(0)?)))?;
+        rows.collect()
+    }
+
+    /// Saves an .ics file served by the `http` host, returning its ID. A file with the same
+    /// `key` as an earlier one replaces it, keeping its ID.
+    pub fn put_ics(&self, key: &str, ics: &str) -> rusqlite::Result<String> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "INSERT INTO ics_files (id, key, ics, updated_at) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (key) DO UPDATE SET ics = excluded.ics, updated_at = excluded.updated_at
+            RETURNING id",
+            params![
+                Uuid::new_v4().simple().to_string(),
+                key,
+                ics,
+                Utc::now().to_rfc3339()
+            ],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn ics_file(&self, id: &str) -> rusqlite::Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT ics FROM ics_files WHERE id = ?1", [id], |row| {
+            row.get(0)
+        })
+        .optional()
+    }
+
+    /// The token of a feed, created with `name` the first time it's asked for.
+    pub fn feed_token(
+        &self,
+        guild_id: GuildId,
+        channel_id: Option<ChannelId>,
+        name: &str,
+    ) -> rusqlite::Result<String> {
+        let conn = self.conn.lock().unwrap();
+        let guild_id = guild_id.get();
+        let channel_id = channel_id.map(|id| id.get());
+        let existing = conn
+            .query_row(
+                "SELECT token FROM feeds WHERE guild_id = ?1 AND channel_id IS ?2",
+                params![guild_id, channel_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(token) = existing {
+            return Ok(token);
+        }
+
+        let token = Uuid::new_v4().simple().to_string();
+        conn.execute(
+            "INSERT INTO feeds (token, guild_id, channel_id, name, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![token, guild_id, channel_id, name, Utc::now().to_rfc3339()],
+        )?;
+        Ok(token)
+    }
+
+    /// Stops a feed's current link working. Asking for it again gives it a new one.
+    pub fn reset_feed(
+        &self,
+        guild_id: GuildId,
+        channel_id: Option<ChannelId>,
+    ) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM feeds WHERE guild_id = ?1 AND channel_id IS ?2",
+            params![guild_id.get(), channel_id.map(|id| id.get())],
+        )?;
+        Ok(())
+    }
+
+    pub fn feed(&self, token: &str) -> rusqlite::Result<Option<Feed>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT guild_id, channel_id, name FROM feeds WHERE token = ?1",
+            [token],
+            |row| {
+                Ok(Feed {
+                    guild_id: GuildId::new(row.get(0)?),
+                    channel_id: row.get::<_, Option<u64>>(1)?.map(ChannelId::new),
+                    name: row.get(2)?,
+                })
+            },
+        )
+        .optional()
+    }
+
+    /// The VEVENT of every event in a feed, oldest first.
+    pub fn feed_events(&self, feed: &Feed) -> rusqlite::Result<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT ics FROM events
+            WHERE guild_id = ?1 AND (?2 IS NULL OR channel_id = ?2) AND NOT private
+            ORDER BY id",
+        )?;
+        let rows = stmt.query_map(
+            params![feed.guild_id.get(), feed.channel_id.map(|id| id.get())],
+            |row| row.get(0),
+        )?;
+        rows.collect()
+    }
+}
+
+/// An event recorded as its VEVENT.
+pub fn parse_event(ics: &str) -> Option<Event> {
+    match CalendarComponent::from_str(ics) {
+        Ok(CalendarComponent::Event(event)) => Some(event),
+        _ => {
+            println!("Skipping unreadable stored event: {ics}");
+            None
+        }
+    }
+}
+
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    let applied: usize = tx.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for migration in MIGRATIONS.iter().skip(applied) {
+        tx.execute_batch(migration)?;
+    }
+    tx.pragma_update(None, "user_version", MIGRATIONS.len())?;
+    tx.commit()
+}
+
+/// `2025-09-21`, `2025-09-21T16:00:00` or `2025-09-21T16:00:00Z`, and the time zone if any.
+fn iso_date_time(time: DatePerhapsTime) -> (String, Option<String>) {
+    match time {
+        DatePerhapsTime::Date(date) => (date.format("%Y-%m-%d").to_string(), None),
+        DatePerhapsTime::DateTime(CalendarDateTime::Floating(dt)) => {
+            (dt.format("%Y-%m-%dT%H:%M:%S").to_string(), None)
+        }
+        DatePerhapsTime::DateTime(CalendarDateTime::Utc(dt)) => {
+            (dt.format("%Y-%m-%dT%H:%M:%SZ").to_string(), None)
+        }
+        DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone { date_time, tzid }) => (
+            date_time.format("%Y-%m-%dT%H:%M:%S").to_string(),
+            Some(tzid),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn store() -> Store {
+        Store::new(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    /// Converted in channel 2 of guild 1 for user 4, from announcement `source` if there is one.
+    fn origin(source: Option<u64>) -> Origin {
+        Origin {
+            guild_id: Some(GuildId::new(1)),
+            channel_id: ChannelId::new(2),
+            source: source.map(MessageId::new),
+            author: UserId::new(4),
+            private: false,
+        }
+    }
+
+    fn urls() -> CalendarUrls {
+        CalendarUrls {
+            all: "all.ics".to_owned(),
+            events: Vec::new(),
+        }
+    }
+
+    fn events(calendar: &Calendar) -> Vec<Event> {
+        calendar
+            .components
+            .iter()
+            .filter_map(|c| c.as_event().cloned())
+            .collect()
+    }
+
+    #[test]
+    fn migrations_apply_once() {
+        let store = store();
+        let mut conn = store.conn.into_inner().unwrap();
+        // reopening an up-to-date database doesn't run anything again
+        migrate(&mut conn).unwrap();
+        let version: usize = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+    }
+
+    #[test]
+    fn ics_files() {
+        let store = store();
+        let id = store.put_ics("a", "BEGIN:VCALENDAR").unwrap();
+        let other = store.put_ics("a,b", "BEGIN:VCALENDAR").unwrap();
+        assert_ne!(id, other);
+        assert_eq!(store.put_ics("a", "edited").unwrap(), id);
+        assert_eq!(store.ics_file(&id).unwrap().as_deref(), Some("edited"));
+        assert_eq!(store.ics_file("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn feeds() {
+        let store = store();
+        let guild = GuildId::new(1);
+        let channel = Some(ChannelId::new(2));
+        let token = store.feed_token(guild, None, "ACM").unwrap();
+        assert_eq!(store.feed_token(guild, None, "ACM").unwrap(), token);
+        let channel_token = store.feed_token(guild, channel, "ACM #events").unwrap();
+        assert_ne!(channel_token, token);
+        assert_eq!(
+            store.feed(&channel_token).unwrap(),
+            Some(Feed {
+                guild_id: guild,
+                channel_id: channel,
+                name: "ACM #events".to_owned(),
+            })
+        );
+
+        store.reset_feed(guild, None).unwrap();
+        assert_eq!(store.feed(&token).unwrap(), None);
+        assert_ne!(store.feed_token(guild, None, "ACM").unwrap(), token);
+        assert!(store.feed(&channel_token).unwrap().is_some());
+
+        let event = |uid: &str| {
+            Event::new()
+                .uid(uid)
+                .all_day(Utc::now().date_naive())
+                .done()
+        };
+        let other_channel = Origin {
+            channel_id: ChannelId::new(3),
+            ..origin(None)
+        };
+        let reply = MessageId::new(5);
+        store
+            .record(&origin(None), reply, &[event("a")], &urls())
+            .unwrap();
+        store
+            .record(&other_channel, reply, &[event("b")], &urls())
+            .unwrap();
+        let uids = |feed: &Feed| -> Vec<bool> {
+            let events = store.feed_events(feed).unwrap();
+            ["UID:a", "UID:b"]
+                .iter()
+                .map(|uid| events.iter().any(|e| e.contains(uid)))
+                .collect()
+        };
+        let whole = store.feed(&store.feed_token(guild, None, "ACM").unwrap());
+        assert_eq!(uids(&whole.unwrap().unwrap()), [true, true]);
+        let events = store.feed(&channel_token).unwrap().unwrap();
+        assert_eq!(uids(&events), [true, false]);
+    }
+
+    #[test]
+    fn identify_and_update() {
+        let store = store();
+        let origin = origin(Some(3));
+        let day = NaiveDate::from_ymd_opt(2025, 9, 21).unwrap();
+        let calendar = |titles: &[&str]| {
+            let mut calendar = Calendar::new();
+            for title in titles {
+                calendar.push(
+                    Event::new()
+                        .uid("random")
+                        .summary(title)
+                        .all_day(day)
+                        .done(),
+                );
+            }
+            calendar.done()
+        };
+        let mut first = calendar(&["Meeting", "Social"]);
+        store.identify(&mut first, &origin).unwrap();
+        let first = events(&first);
+        let uids: Vec<_> = first.iter().map(|e| e.get_uid().unwrap()).collect();
+        assert_eq!(uids, ["1-3-0@calbot", "1-3-1@calbot"]);
+        assert_eq!(first[0].get_sequence(), Some(0));
+        assert!(first[0].get_timestamp().is_some());
+        store
+            .record(&origin, MessageId::new(5), &first, &urls())
+            .unwrap();
+
+        // the announcement is edited down to one event
+        let mut edited = calendar(&["Meeting (moved)"]);
+        store.identify(&mut edited, &origin).unwrap();
+        let edited = events(&edited);
+        assert_eq!(edited[0].get_uid(), Some("1-3-0@calbot"));
+        assert_eq!(edited[0].get_sequence(), Some(1));
+        store
+            .record(&origin, MessageId::new(5), &edited, &urls())
+            .unwrap();
+
+        let converted = store.converted(MessageId::new(3)).unwrap().unwrap();
+        assert_eq!(converted.reply, MessageId::new(5));
+        assert_eq!(converted.sequence, 1);
+        assert_eq!(converted.origin.author, origin.author);
+        let conn = store.conn.lock().unwrap();
+        let titles: Vec<String> = conn
+            .prepare("SELECT title FROM events")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(titles, ["Meeting (moved)"]);
+        drop(conn);
+
+        // the announcement is deleted
+        let mut cancelled = store.source_events(MessageId::new(3)).unwrap();
+        assert_eq!(cancelled.len(), 1);
+        assert_eq!(cancelled[0].get_summary(), Some("Meeting (moved)"));
+        cancelled[0].status(EventStatus::Cancelled);
+        store
+            .record(&origin, MessageId::new(5), &cancelled, &urls())
+            .unwrap();
+        let conn = store.conn.lock().unwrap();
+        let cancelled_at: Option<String> = conn
+            .query_row("SELECT cancelled_at FROM events", [], |row| row.get(0))
+            .unwrap();
+        assert!(cancelled_at.is_some());
+        drop(conn);
+
+        // without an announcement there's nothing to derive a UID from
+        let mut command = calendar(&["Meeting"]);
+        let from_command = Origin {
+            source: None,
+            ..origin
+        };
+        store.identify(&mut command, &from_command).unwrap();
+        assert_eq!(events(&command)[0].get_uid(), Some("random"));
+        assert!(store.converted(MessageId::new(6)).unwrap().is_none());
+    }
+
+    #[test]
+    fn settings_and_scheduled_events() {
+        let store = store();
+        let guild = GuildId::new(1);
+        assert_eq!(store.settings(guild).unwrap(), GuildSettings::default());
+        let on = GuildSettings {
+            discord_events: true,
+            import_channel: Some(ChannelId::new(7)),
+            reaction_emoji: Some("🗓️".to_owned()),
+        };
+        store.save_settings(guild, &on).unwrap();
+        assert_eq!(store.settings(guild).unwrap(), on);
+        assert_eq!(on.reaction_emoji(), "🗓️");
+        let default = store.settings(GuildId::new(2)).unwrap();
+        assert_eq!(default, GuildSettings::default());
+        assert_eq!(default.reaction_emoji(), reactions::DEFAULT_EMOJI);
+
+        let events = [Event::new()
+            .uid("a")
+            .all_day(NaiveDate::from_ymd_opt(2025, 9, 21).unwrap())
+            .done()];
+        store
+            .record(&origin(Some(3)), MessageId::new(5), &events, &urls())
+            .unwrap();
+        assert!(store
+            .scheduled_events(MessageId::new(3))
+            .unwrap()
+            .is_empty());
+        store
+            .set_scheduled_event("a", ScheduledEventId::new(6))
+            .unwrap();
+        // converting it again keeps the scheduled event
+        store
+            .record(&origin(Some(3)), MessageId::new(5), &events, &urls())
+            .unwrap();
+        assert_eq!(
+            store.scheduled_events(MessageId::new(3)).unwrap(),
+            [("a".to_owned(), ScheduledEventId::new(6))]
+        );
+        // only events imported from Discord count as imported, not ones made from announcements
+        assert!(store.imported(ScheduledEventId::new(6)).unwrap().is_none());
+
+        let imported = [Event::new()
+            .uid("1-event-8@calbot")
+            .all_day(NaiveDate::from_ymd_opt(2025, 9, 22).unwrap())
+            .done()];
+        store
+            .record(&origin(None), MessageId::new(9), &imported, &urls())
+            .unwrap();
+        store
+            .set_scheduled_event("1-event-8@calbot", ScheduledEventId::new(8))
+            .unwrap();
+        let (converted, event) = store.imported(ScheduledEventId::new(8)).unwrap().unwrap();
+        assert_eq!(converted.reply, MessageId::new(9));
+        assert_eq!(event.get_uid(), Some("1-event-8@calbot"));
+    }
+
+    #[test]
+    fn watched_channels() {
+        let store = store();
+        let guild = GuildId::new(1);
+        let channel = ChannelId::new(2);
+        assert!(!store.is_watched(channel).unwrap());
+        assert!(store.watch(guild, channel, true).unwrap());
+        assert!(!store.watch(guild, channel, true).unwrap());
+        assert!(store.is_watched(channel).unwrap());
+        assert_eq!(store.watched_channels(guild).unwrap(), [channel]);
+        assert!(store.watched_channels(GuildId::new(3)).unwrap().is_empty());
+
+        assert!(store.watch(guild, channel, false).unwrap());
+        assert!(!store.watch(guild, channel, false).unwrap());
+        assert!(!store.is_watched(channel).unwrap());
+    }
+
+    #[test]
+    fn uids_follow_events() {
+        let store = store();
+        let origin = origin(Some(3));
+        let day = |d| NaiveDate::from_ymd_opt(2025, 9, d).unwrap();
+        let convert = |dated: &[(&str, u32)]| -> Vec<Event> {
+            let mut calendar = Calendar::new();
+            for (title, d) in dated {
+                calendar.push(Event::new().summary(title).all_day(day(*d)).done());
+            }
+            let mut calendar = calendar.done();
+            store.identify(&mut calendar, &origin).unwrap();
+            let events = events(&calendar);
+            store
+                .record(&origin, MessageId::new(5), &events, &urls())
+                .unwrap();
+            events
+        };
+        let uids = |events: &[Event]| -> Vec<String> {
+            events
+                .iter()
+                .map(|e| e.get_uid().unwrap().to_owned())
+                .collect()
+        };
+
+        let first = convert(&[("Meeting", 21), ("Social", 22)]);
+        assert_eq!(uids(&first), ["1-3-0@calbot", "1-3-1@calbot"]);
+
+        // a workshop is added first, the social is renamed and the meeting moved
+        let edited = convert(&[("Workshop", 20), ("Meeting", 28), ("Game night", 22)]);
+        assert_eq!(
+            uids(&edited),
+            ["1-3-1-0@calbot", "1-3-0@calbot", "1-3-1@calbot"]
+        );
+
+        // the workshop is dropped, and nothing takes over its UID later
+        let again = convert(&[("Meeting", 28), ("Game night", 22), ("Hike", 27)]);
+        assert_eq!(
+            uids(&again),
+            ["1-3-0@calbot", "1-3-1@calbot", "1-3-2-2@calbot"]
+        );
+    }
+
+    #[test]
+    fn private_grabs_arent_tracked() {
+        let store = store();
+        // "Create calendar event" on message 3, shown only to whoever used it
+        let grab = Origin {
+            private: true,
+            ..origin(None)
+        };
+        let mut calendar = Calendar::new()
+            .push(
+                Event::new()
+                    .uid("random")
+                    .all_day(NaiveDate::from_ymd_opt(2025, 9, 21).unwrap())
+                    .done(),
+            )
+            .done();
+        store.identify(&mut calendar, &grab).unwrap();
+        store
+            .record(&grab, MessageId::new(5), &events(&calendar), &urls())
+            .unwrap();
+
+        // so editing or deleting the announcement leaves them alone
+        assert!(store.converted(MessageId::new(3)).unwrap().is_none());
+        assert!(store.source_events(MessageId::new(3)).unwrap().is_empty());
+
+        // and anyone can grab events, so they mustn't show up in the guild's feeds
+        for channel_id in [None, Some(ChannelId::new(2))] {
+            let feed = Feed {
+                guild_id: GuildId::new(1),
+                channel_id,
+                name: "ACM".to_owned(),
+            };
+            assert!(store.feed_events(&feed).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn record() {
+        let store = store();
+        let start = NaiveDate::from_ymd_opt(2025, 9, 21)
+            .unwrap()
+            .and_hms_opt(16, 0, 0)
+            .unwrap();
+        let events = [
+            Event::new()
+                .uid("a")
+                .summary("ACM General Meeting")
+                .starts(CalendarDateTime::from_date_time(
+                    start
+                        .and_local_timezone(chrono_tz::America::Los_Angeles)
+                        .unwrap(),
+                ))
+                .location("OCNL 241")
+                .add_property("RRULE", "FREQ=WEEKLY")
+                .done(),
+            Event::new()
+                .uid("b")
+                .summary("Hackathon")
+                .all_day(start.date())
+                .done(),
+        ];
+        let urls = CalendarUrls {
+            events: vec!["1.ics".to_owned(), "2.ics".to_owned()],
+            ..urls()
+        };
+        store
+            .record(&origin(Some(3)), MessageId::new(5), &events, &urls)
+            .unwrap();
+
+        let conn = store.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT uid, guild_id, source_message_id, reply_message_id, title, starts_at,
+                    timezone, location, rrule, attachment_url
+                FROM events ORDER BY id",
+            )
+            .unwrap();
+        let rows: Vec<_> = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<u64>>(1)?,
+                    row.get::<_, Option<u64>>(2)?,
+                    row.get::<_, u64>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, String>(5)?,
+                    row.get::<_, Option<String>>(6)?,
+                    row.get::<_, Option<String>>(7)?,
+                    row.get::<_, Option<String>>(8)?,
+                    row.get::<_, String>(9)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            [
+                (
+                    "a".to_owned(),
+                    Some(1),
+                    Some(3),
+                    5,
+                    "ACM General Meeting".to_owned(),
+                    "2025-09-21T16:00:00".to_owned(),
+                    Some("America/Los_Angeles".to_owned()),
+                    Some("OCNL 241".to_owned()),
+                    Some("FREQ=WEEKLY".to_owned()),
+                    "1.ics".to_owned(),
+                ),
+                (
+                    "b".to_owned(),
+                    Some(1),
+                    Some(3),
+                    5,
+                    "Hackathon".to_owned(),
+                    "2025-09-21".to_owned(),
+                    None,
+                    None,
+                    None,
+                    "2.ics".to_owned(),
+                ),
+            ]
+        );
+    }
+}
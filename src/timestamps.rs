@@ -0,0 +1,109 @@
+//! Discord's `<t:unix:style>` timestamp tags, which every reader sees in their own time zone.
+//!
+//! Neither the LLM nor the rules can decode Unix seconds, so the tags are rewritten into plain
+//! dates and times before extraction, and the exact instants are kept to pin the extracted
+//! events to afterwards.
+
+use std::sync::LazyLock;
+
+use chrono::{DateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use regex::Regex;
+
+use crate::timezone;
+
+static TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<t:(-?\d+)(?::([tTdDfFR]))?>").unwrap());
+
+pub struct Timestamp {
+    pub at: DateTime<Utc>,
+    /// Discord's style suffix, `f` when the tag has none
+    pub style: char,
+}
+
+impl Timestamp {
+    /// `d` and `D` only show the date, even though the tag is an exact instant.
+    pub fn has_time(&self) -> bool {
+        !matches!(self.style, 'd' | 'D')
+    }
+}
+
+/// A message with its timestamp tags written out.
+pub struct Resolved {
+    pub text: String,
+    /// Every tag in the message, in order
+    pub stamps: Vec<Timestamp>,
+}
+
+/// Rewrites every timestamp tag in `msg` as it would be shown to a reader in `tz`, e.g.
+/// `<t:1738800000:F>` becomes "2/5/2025 4:00 PM PST" for America/Los_Angeles.
+///
+/// Relative (`R`) tags are written out in full, since "in 2 days" depends on when it's read.
+pub fn resolve(msg: &str, tz: Tz) -> Resolved {
+    let mut stamps = Vec::new();
+    let text = TAG.replace_all(msg, |caps: &regex::Captures| {
+        let Some(at) = caps[1]
+            .parse()
+            .ok()
+            .and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+        else {
+            return caps[0].to_owned();
+        };
+        let style = caps
+            .get(2)
+            .and_then(|s| s.as_str().chars().next())
+            .unwrap_or('f');
+        let local = at.with_timezone(&tz);
+        let zone = timezone::abbreviation(tz, &local.naive_local());
+        stamps.push(Timestamp { at, style });
+
+        match style {
+            't' => format!("{} {zone}", local.format("%-I:%M %p")),
+            'T' => format!("{} {zone}", local.format("%-I:%M:%S %p")),
+            'd' | 'D' => local.format("%-m/%-d/%Y").to_string(),
+            _ => format!("{} {zone}", local.format("%-m/%-d/%Y %-I:%M %p")),
+        }
+    });
+
+    Resolved {
+        text: text.into_owned(),
+        stamps,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_styles() {
+        let la = chrono_tz::America::Los_Angeles;
+        // 2025-02-06 00:00 UTC
+        let resolved = resolve(
+            "<t:1738800000> <t:1738800000:t> <t:1738800000:T> <t:1738800000:d> \
+             <t:1738800000:D> <t:1738800000:f> <t:1738800000:F> <t:1738800000:R>",
+            la,
+        );
+        assert_eq!(
+            resolved.text,
+            "2/5/2025 4:00 PM PST 4:00 PM PST 4:00:00 PM PST 2/5/2025 \
+             2/5/2025 2/5/2025 4:00 PM PST 2/5/2025 4:00 PM PST 2/5/2025 4:00 PM PST"
+        );
+        let styles: String = resolved.stamps.iter().map(|s| s.style).collect();
+        assert_eq!(styles, "ftTdDfFR");
+        assert!(resolved
+            .stamps
+            .iter()
+            .all(|s| s.at.timestamp() == 1738800000));
+
+        let utc = resolve("Starts <t:1738800000:t>", chrono_tz::UTC);
+        assert_eq!(utc.text, "Starts 12:00 AM UTC");
+    }
+
+    #[test]
+    fn resolve_leaves_other_markup() {
+        let resolved = resolve("<@1234> <t:abc:F> <#5678>", chrono_tz::UTC);
+        assert_eq!(resolved.text, "<@1234> <t:abc:F> <#5678>");
+        assert!(resolved.stamps.is_empty());
+    }
+}
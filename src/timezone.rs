@@ -0,0 +1,222 @@
+use std::env;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::{OffsetComponents, OffsetName, Tz, TzOffset};
+use icalendar::{Calendar, CalendarDateTime, Component, DatePerhapsTime};
+use serenity::all::GuildId;
+
+/// Time zone used for a guild's events.
+///
+/// `CALBOT_TIMEZONES` holds per-guild zones as `guild_id=Zone` pairs separated by commas
+/// (e.g. `1234=America/Los_Angeles,5678=Europe/London`), and `CALBOT_TIMEZONE` is the fallback
+/// for every other guild. Without either, events keep using floating times.
+pub fn guild_timezone(guild_id: Option<GuildId>) -> Option<Tz> {
+    if let (Some(guild_id), Ok(zones)) = (guild_id, env::var("CALBOT_TIMEZONES")) {
+        let guild_zone = zones.split(',').find_map(|pair| {
+            let (id, zone) = pair.split_once('=')?;
+            (id.trim().parse::<u64>().ok()? == guild_id.get()).then(|| parse_zone(zone))?
+        });
+        if guild_zone.is_some() {
+            return guild_zone;
+        }
+    }
+    env::var("CALBOT_TIMEZONE")
+        .ok()
+        .and_then(|zone| parse_zone(&zone))
+}
+
+/// Accepts IANA names ("America/New_York") as well as the abbreviations people actually write
+/// in announcements ("PST", "EDT", "UTC").
+pub fn parse_zone(zone: &str) -> Option<Tz> {
+    let zone = zone.trim();
+    let iana = match zone.to_uppercase().as_str() {
+        "UTC" | "GMT" | "Z" => "UTC",
+        "PST" | "PDT" | "PT" => "America/Los_Angeles",
+        "MST" | "MDT" | "MT" => "America/Denver",
+        "CST" | "CDT" | "CT" => "America/Chicago",
+        "EST" | "EDT" | "ET" => "America/New_York",
+        "AKST" | "AKDT" => "America/Anchorage",
+        "HST" => "Pacific/Honolulu",
+        "BST" => "Europe/London",
+        "CET" | "CEST" => "Europe/Paris",
+        "IST" => "Asia/Kolkata",
+        "JST" => "Asia/Tokyo",
+        "AEST" | "AEDT" => "Australia/Sydney",
+        _ => zone,
+    };
+    iana.parse().ok()
+}
+
+/// Abbreviation of the zone at a local time, e.g. "PST" or "PDT" for America/Los_Angeles.
+pub fn abbreviation(tz: Tz, local: &NaiveDateTime) -> String {
+    let offset = tz
+        .offset_from_local_datetime(local)
+        .earliest()
+        .unwrap_or_else(|| tz.offset_from_utc_datetime(local));
+    match offset.abbreviation() {
+        Some(abbr) => abbr.to_owned(),
+        None => format_offset(offset.fix().local_minus_utc()),
+    }
+}
+
+fn format_offset(secs: i32) -> String {
+    let sign = if secs < 0 { '-' } else { '+' };
+    let secs = secs.abs();
+    format!("{sign}{:02}{:02}", secs / 3600, secs % 3600 / 60)
+}
+
+/// Serializes a calendar to .ics, adding the VTIMEZONE definition of every TZID its events use.
+///
+/// `icalendar` stamps a DTSTAMP and UID onto every component it writes, neither of which is
+/// allowed in a VTIMEZONE, so the definitions are spliced into the text instead.
+pub fn to_ics(calendar: &Calendar) -> String {
+    // zone -> first and last date it's used on
+    let mut zones: Vec<(Tz, NaiveDate, NaiveDate)> = Vec::new();
+    let times = calendar
+        .components
+        .iter()
+        .filter_map(|c| c.as_event())
+        .flat_map(|e| [e.get_start(), e.get_end()])
+        .flatten();
+    for dt in times {
+        let DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone { date_time, tzid }) = dt
+        else {
+            continue;
+        };
+        let Ok(tz) = tzid.parse::<Tz>() else {
+            continue;
+        };
+        let date = date_time.date();
+        match zones.iter_mut().find(|(zone, _, _)| *zone == tz) {
+            Some((_, from, to)) => {
+                *from = (*from).min(date);
+                *to = (*to).max(date);
+            }
+            None => zones.push((tz, date, date)),
+        }
+    }
+
+    let ics = calendar.to_string();
+    if zones.is_empty() {
+        return ics;
+    }
+    let vtimezones: String = zones
+        .into_iter()
+        .map(|(tz, from, to)| vtimezone(tz, from, to))
+        .collect();
+    // definitions go right after the calendar's own properties
+    let at = ics
+        .find("BEGIN:VEVENT")
+        .or_else(|| ics.rfind("END:VCALENDAR"))
+        .expect("Calendar should be terminated");
+    format!("{}{}{}", &ics[..at], vtimezones, &ics[at..])
+}
+
+/// The VTIMEZONE definition for `tz`, covering the years around `from` to `to`.
+///
+/// Rather than deriving RRULEs for the zone's DST rules, every transition in the window is listed
+/// as its own observance, which is just as valid and can't disagree with the tz database.
+fn vtimezone(tz: Tz, from: NaiveDate, to: NaiveDate) -> String {
+    let window_start = Utc
+        .with_ymd_and_hms(from.year() - 1, 1, 1, 0, 0, 0)
+        .unwrap();
+    let window_end = Utc.with_ymd_and_hms(to.year() + 2, 1, 1, 0, 0, 0).unwrap();
+    let offset_at = |t: DateTime<Utc>| tz.offset_from_utc_datetime(&t.naive_utc());
+
+    let mut prev = offset_at(window_start);
+    let mut lines = vec!["BEGIN:VTIMEZONE".to_owned(), format!("TZID:{}", tz.name())];
+    push_observance(&mut lines, window_start, &prev, &prev);
+
+    let mut day = window_start;
+    while day < window_end {
+        let next_day = day + Duration::days(1);
+        let next = offset_at(next_day);
+        if next != prev {
+            // find the first minute of the day using the new offset
+            let (mut lo, mut hi) = (0, 24 * 60);
+            while hi - lo > 1 {
+                let mid = (lo + hi) / 2;
+                if offset_at(day + Duration::minutes(mid)) == prev {
+                    lo = mid;
+                } else {
+                    hi = mid;
+                }
+            }
+            push_observance(&mut lines, day + Duration::minutes(hi), &prev, &next);
+            prev = next;
+        }
+        day = next_day;
+    }
+    lines.push("END:VTIMEZONE".to_owned());
+
+    lines.into_iter().map(|line| line + "\r\n").collect()
+}
+
+fn push_observance(lines: &mut Vec<String>, at: DateTime<Utc>, from: &TzOffset, to: &TzOffset) {
+    let kind = if to.dst_offset().is_zero() {
+        "STANDARD"
+    } else {
+        "DAYLIGHT"
+    };
+    // DTSTART of an observance is the local time according to the offset being replaced
+    let local = at.naive_utc() + Duration::seconds(from.fix().local_minus_utc().into());
+
+    lines.push(format!("BEGIN:{kind}"));
+    lines.push(format!("DTSTART:{}", local.format("%Y%m%dT%H%M%S")));
+    lines.push(format!(
+        "TZOFFSETFROM:{}",
+        format_offset(from.fix().local_minus_utc())
+    ));
+    lines.push(format!(
+        "TZOFFSETTO:{}",
+        format_offset(to.fix().local_minus_utc())
+    ));
+    if let Some(abbr) = to.abbreviation() {
+        lines.push(format!("TZNAME:{abbr}"));
+    }
+    lines.push(format!("END:{kind}"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use icalendar::EventLike;
+
+    #[test]
+    fn parse_zone_abbreviations() {
+        assert_eq!(parse_zone("PST"), Some(chrono_tz::America::Los_Angeles));
+        assert_eq!(parse_zone("utc"), Some(chrono_tz::UTC));
+        assert_eq!(parse_zone("Europe/Berlin"), Some(chrono_tz::Europe::Berlin));
+        assert_eq!(parse_zone("Mars/Olympus_Mons"), None);
+    }
+
+    #[test]
+    fn to_ics_vtimezone_transitions() {
+        let mut calendar = Calendar::new();
+        calendar.push(
+            icalendar::Event::new()
+                .starts(CalendarDateTime::WithTimezone {
+                    date_time: NaiveDate::from_ymd_opt(2025, 3, 14)
+                        .unwrap()
+                        .and_hms_opt(17, 0, 0)
+                        .unwrap(),
+                    tzid: "America/Los_Angeles".to_owned(),
+                })
+                .done(),
+        );
+        let ics = to_ics(&calendar);
+
+        let vtz_start = ics
+            .find("BEGIN:VTIMEZONE\r\nTZID:America/Los_Angeles")
+            .unwrap();
+        assert!(vtz_start < ics.find("BEGIN:VEVENT").unwrap());
+        // DST started on March 9th 2025 at 2am local time
+        assert!(ics.contains(
+            "BEGIN:DAYLIGHT\r\nDTSTART:20250309T020000\r\nTZOFFSETFROM:-0800\r\nTZOFFSETTO:-0700\r\nTZNAME:PDT"
+        ));
+        // and ended on November 2nd 2025 at 2am local (daylight) time
+        assert!(ics.contains(
+            "BEGIN:STANDARD\r\nDTSTART:20251102T020000\r\nTZOFFSETFROM:-0700\r\nTZOFFSETTO:-0800\r\nTZNAME:PST"
+        ));
+    }
+}
@@ -1,76 +1,225 @@
-use std::env;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use chrono_tz::Tz;
+use icalendar::{Calendar, CalendarDateTime, Component, DatePerhapsTime, Event, EventLike};
+use serenity::all::{CreateActionRow, CreateButton, MessageBuilder};
 
-use icalendar::{Calendar, CalendarDateTime, Component, DatePerhapsTime, EventLike};
-use serenity::all::{ChannelId, Context, CreateAttachment, CreateMessage, MessageBuilder};
+use crate::{
+    parser::{Error, MAX_EVENT_DAYS},
+    recurrence::RecurrenceRule,
+    timezone,
+};
 
-pub async fn upload_calendar(ctx: &Context, calendar: &Calendar) -> String {
-    // returns a url to the uploaded .ics file
-    let priv_chan = ChannelId::new(
-        env::var("CALBOT_CHAN")
-            .expect("CALBOT_CHAN missing")
-            .parse()
-            .expect("Invalid CALBOT_CHAN"),
-    );
-
-    let attachment = CreateAttachment::bytes(calendar.to_string().as_bytes(), "CalBot.ics");
-    let message = CreateMessage::new().add_file(attachment);
+/// The guild's local date at `at`, which relative dates like "tomorrow" are relative to.
+pub fn local_date(at: DateTime<Utc>, tz: Option<Tz>) -> NaiveDate {
+    match tz {
+        Some(tz) => at.with_timezone(&tz).date_naive(),
+        None => at.date_naive(),
+    }
+}
 
-    let sent = priv_chan
-        .send_message(ctx, message)
-        .await
-        .expect("Failed to send message");
+/// Links to the uploaded .ics files for a parsed announcement.
+#[derive(Clone)]
+pub struct CalendarUrls {
+    /// A single .ics with every event
+    pub all: String,
+    /// One .ics per event, in the same order as the calendar's components. Empty when the
+    /// calendar only has one event, since `all` already covers it.
+    pub events: Vec<String>,
+}
 
-    sent.attachments
-        .first()
-        .expect("Bot should have added an attachment")
-        .url
-        .to_owned()
+pub fn calendar_events(calendar: &Calendar) -> Vec<&Event> {
+    calendar
+        .components
+        .iter()
+        .filter_map(|c| c.as_event())
+        .collect()
 }
 
 pub fn calendar_message(calendar: &Calendar, mb: &mut MessageBuilder) {
-    let event = calendar
-        .components
-        .first()
-        .expect("Generated Calendar should have an event")
-        .as_event()
-        .expect("Generated Calendar should have an event");
+    let events = calendar_events(calendar);
+    assert!(
+        !events.is_empty(),
+        "Generated Calendar should have an event"
+    );
 
-    let start_dt = if let DatePerhapsTime::DateTime(dt) = event
-        .get_start()
-        .expect("Parsing should ensure this is Some")
-    {
-        if let CalendarDateTime::Floating(dt) = dt {
-            dt
-        } else {
-            panic!("Start time should be a floating time");
+    for (i, event) in events.into_iter().enumerate() {
+        if i > 0 {
+            mb.push_line("");
         }
-    } else {
-        panic!("Start time should be a DateTime");
-    };
+        event_message(event, mb);
+    }
+}
 
-    let end_dt = if let DatePerhapsTime::DateTime(dt) =
-        event.get_end().expect("Parsing should ensure this is Some")
-    {
-        if let CalendarDateTime::Floating(dt) = dt {
-            dt
-        } else {
-            panic!("Start time should be a floating time");
+/// Splits an event boundary into its date and, unless it's an all-day event, its time of day.
+pub fn date_and_time(dpt: DatePerhapsTime) -> (NaiveDate, Option<NaiveTime>) {
+    match dpt {
+        DatePerhapsTime::Date(date) => (date, None),
+        DatePerhapsTime::DateTime(CalendarDateTime::Floating(dt)) => (dt.date(), Some(dt.time())),
+        DatePerhapsTime::DateTime(CalendarDateTime::Utc(dt)) => (dt.date_naive(), Some(dt.time())),
+        DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone { date_time, .. }) => {
+            (date_time.date(), Some(date_time.time()))
         }
-    } else {
-        panic!("Start time should be a DateTime");
-    };
+    }
+}
+
+/// Abbreviation of the zone a time is in, e.g. " PDT". Floating times and dates have none.
+fn zone_suffix(dpt: &DatePerhapsTime) -> String {
+    match dpt {
+        DatePerhapsTime::DateTime(CalendarDateTime::Utc(_)) => " UTC".to_owned(),
+        DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone { date_time, tzid }) => {
+            match tzid.parse::<Tz>() {
+                Ok(tz) => format!(" {}", timezone::abbreviation(tz, date_time)),
+                Err(_) => format!(" {tzid}"),
+            }
+        }
+        _ => String::new(),
+    }
+}
+
+fn event_message(event: &Event, mb: &mut MessageBuilder) {
+    let start = event
+        .get_start()
+        .expect("Parsing should ensure this is Some");
+    let end = event.get_end().expect("Parsing should ensure this is Some");
+    let (start_date, start_time) = date_and_time(start.clone());
+    let (mut end_date, end_time) = date_and_time(end.clone());
+    let (start_zone, end_zone) = (zone_suffix(&start), zone_suffix(&end));
+    if end_time.is_none() {
+        // DTEND of an all-day event is the (exclusive) day after it ends
+        end_date = end_date.pred_opt().unwrap_or(end_date).max(start_date);
+    }
 
     mb.push_quote_safe("**Event Name**: ")
-        .push_line_safe(event.get_summary().expect("Event should have a summary"))
-        .push_quote_safe("**Date**: ")
-        .push_line_safe(start_dt.date().format("%A, %b %e, %Y").to_string())
-        .push_quote_safe("**Start Time**: ")
-        .push_line_safe(start_dt.time().format("%l:%M %p").to_string())
-        .push_quote_safe("**End Time**: ")
-        .push_line_safe(end_dt.time().format("%l:%M %p").to_string())
-        .push_quote_safe("**Location**: ")
+        .push_line_safe(event.get_summary().expect("Event should have a summary"));
+    match (start_time, end_time) {
+        (Some(start_time), Some(end_time)) if start_date == end_date => {
+            mb.push_quote_safe("**Date**: ")
+                .push_line_safe(start_date.format("%A, %b %e, %Y").to_string())
+                .push_quote_safe("**Start Time**: ")
+                .push_line_safe(format!("{}{start_zone}", start_time.format("%l:%M %p")))
+                .push_quote_safe("**End Time**: ")
+                .push_line_safe(format!("{}{end_zone}", end_time.format("%l:%M %p")));
+        }
+        (Some(start_time), Some(end_time)) => {
+            mb.push_quote_safe("**Starts**: ")
+                .push_line_safe(format!(
+                    "{}{start_zone}",
+                    start_date
+                        .and_time(start_time)
+                        .format("%A, %b %e, %Y at %l:%M %p")
+                ))
+                .push_quote_safe("**Ends**: ")
+                .push_line_safe(format!(
+                    "{}{end_zone}",
+                    end_date
+                        .and_time(end_time)
+                        .format("%A, %b %e, %Y at %l:%M %p")
+                ));
+        }
+        _ if start_date == end_date => {
+            mb.push_quote_safe("**Date**: ")
+                .push_line_safe(start_date.format("%A, %b %e, %Y").to_string())
+                .push_quote_safe("**Time**: ")
+                .push_line_safe("All day");
+        }
+        _ => {
+            mb.push_quote_safe("**Starts**: ")
+                .push_line_safe(start_date.format("%A, %b %e, %Y").to_string())
+                .push_quote_safe("**Ends**: ")
+                .push_line_safe(end_date.format("%A, %b %e, %Y").to_string())
+                .push_quote_safe("**Time**: ")
+                .push_line_safe("All day");
+        }
+    }
+    match &start {
+        DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone { tzid, .. }) => {
+            mb.push_quote_safe("**Time Zone**: ").push_line_safe(tzid);
+        }
+        DatePerhapsTime::DateTime(CalendarDateTime::Utc(_)) => {
+            mb.push_quote_safe("**Time Zone**: ").push_line_safe("UTC");
+        }
+        _ => {}
+    }
+    mb.push_quote_safe("**Location**: ")
         .push_line_safe(event.get_location().unwrap_or("None"));
+    if let Some(rule) = event
+        .property_value("RRULE")
+        .and_then(|rrule| RecurrenceRule::from_rrule(rrule, &start))
+    {
+        mb.push_quote_safe("**Repeats**: ")
+            .push_line_safe(rule.describe());
+    }
+    if let Some(exdates) = event.multi_properties().get("EXDATE") {
+        let skipped: Vec<_> = exdates
+            .iter()
+            .filter_map(|p| NaiveDate::parse_from_str(p.value().get(..8)?, "%Y%m%d").ok())
+            .map(|d| d.format("%a, %b %e").to_string())
+            .collect();
+        mb.push_quote_safe("**Except**: ")
+            .push_line_safe(skipped.join(", "));
+    }
     if let Some(desc) = event.get_description() {
         mb.push_quote_safe("**Description**: ").push_line_safe(desc);
     }
 }
+
+/// "Add to iCal" for a single event, otherwise one "Add <title>" button per event and an
+/// "Add all to iCal" button, five to a row.
+pub fn calendar_buttons(calendar: &Calendar, urls: CalendarUrls) -> Vec<CreateActionRow> {
+    let mut buttons = Vec::new();
+    if urls.events.is_empty() {
+        buttons.push(CreateButton::new_link(urls.all).label("Add to iCal"));
+    } else {
+        for (url, event) in urls.events.into_iter().zip(calendar_events(calendar)) {
+            let title = event.get_summary().unwrap_or("Event");
+            buttons.push(CreateButton::new_link(url).label(button_label("Add", title)));
+        }
+        buttons.push(CreateButton::new_link(urls.all).label("Add all to iCal"));
+    }
+    buttons
+        .chunks(5)
+        .map(|row| CreateActionRow::Buttons(row.to_vec()))
+        .collect()
+}
+
+/// What to tell the user when a message couldn't be turned into events. `None` for errors that
+/// aren't their fault and are only logged.
+pub fn error_reply(e: &Error) -> Option<String> {
+    match e {
+        Error::ParseFailure => Some("Sorry! I couldn't parse that message.".to_owned()),
+        Error::InvalidOutput { field, reason } => {
+            println!("Invalid `{field}` in LLM output: {reason}");
+            Some(format!(
+                "Sorry! I couldn't parse that message (`{field}`: {reason})."
+            ))
+        }
+        Error::InvalidDuration { start, end } => {
+            let reason = if end < start {
+                "it would end before it starts".to_owned()
+            } else {
+                format!("it would run for more than {MAX_EVENT_DAYS} days")
+            };
+            Some(format!("Sorry! That event doesn't look right, {reason}."))
+        }
+        Error::NoResponse => Some("Sorry! The LLM didn't respond. Try again later.".to_owned()),
+        Error::Reqwest(e) => {
+            println!("Error: {e}");
+            None
+        }
+        Error::Publish(e) => {
+            println!("Error publishing calendar: {e}");
+            Some("Sorry! I couldn't publish the calendar. Try again later.".to_owned())
+        }
+    }
+}
+
+/// Discord rejects button labels longer than 80 characters.
+pub fn button_label(verb: &str, title: &str) -> String {
+    let label = format!("{verb} {title}");
+    if label.chars().count() <= 80 {
+        return label;
+    }
+    let mut short: String = label.chars().take(79).collect();
+    short.push('…');
+    short
+}
@@ -0,0 +1,25 @@
+//! Announcement channels, whose messages are converted without mentioning the bot.
+//!
+//! Admins pick them with `/watch on channel:#events`. Every new message there goes through
+//! `parser::detect_event`, and ones that look like an event get a reply with their "Add to iCal"
+//! buttons straight away, see `Handler::watch`. Posters can keep a message from being converted by
+//! putting [`OPT_OUT`] in it.
+
+/// Leaves a message in a watched channel alone when it's anywhere in it, in any case.
+pub const OPT_OUT: &str = "[nocal]";
+
+pub fn opted_out(content: &str) -> bool {
+    content.to_lowercase().contains(OPT_OUT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opt_out() {
+        assert!(opted_out("Officer meeting tomorrow at 5 [nocal]"));
+        assert!(opted_out("[NoCal] Officer meeting tomorrow at 5"));
+        assert!(!opted_out("Officer meeting tomorrow at 5"));
+    }
+}